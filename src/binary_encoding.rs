@@ -0,0 +1,249 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::settings::Settings;
+
+
+/// Every binary encoding starts with these bytes, text encodings never do.
+pub const MAGIC: &[u8; 4] = b"SPWC";
+pub const VERSION: u16 = 1;
+
+/// The header of a binary encoding, it tells what the model is without reading the pattern trees.
+/// Fixed size numbers are little endian, lengths, letters and counts are LEB128 varints. After the
+/// header follow the pattern trees, each as the number of its patterns and for every pattern its
+/// UTF-8 length, its bytes, the number of its followers and for every follower its letter and count.
+/// The file ends with the CRC-32 of everything before it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metadata {
+    pub version: u16,
+    /// The number of pattern trees.
+    pub order: u32,
+    /// How many passwords the model was trained on, 0 for models without an end symbol.
+    pub training_size: u64,
+    /// Seconds since the Unix epoch.
+    pub created: u64,
+    pub alphabet: Vec<char>,
+    pub settings: Settings,
+}
+
+impl Metadata {
+    pub fn new(order: usize, training_size: u64, alphabet: Vec<char>, settings: Settings) -> Self {
+        let created = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+        Self {
+            version: VERSION,
+            order: order as u32,
+            training_size,
+            created,
+            alphabet,
+            settings,
+        }
+    }
+
+    pub fn write(&self, output: &mut Vec<u8>) {
+        output.extend_from_slice(MAGIC);
+        output.extend_from_slice(&self.version.to_le_bytes());
+        output.extend_from_slice(&self.order.to_le_bytes());
+        output.extend_from_slice(&self.training_size.to_le_bytes());
+        output.extend_from_slice(&self.created.to_le_bytes());
+        write_u32(output, self.alphabet.len() as u32);
+        for letter in self.alphabet.iter() {
+            write_u32(output, *letter as u32);
+        }
+        write_str(output, &self.settings.encoding_lines().join("\n"));
+    }
+
+    pub fn read(input: &mut BinaryReader) -> Result<Self, io::Error> {
+        if input.read_bytes(MAGIC.len())? != MAGIC {
+            return Err(invalid_data("Not a binary encoding".to_string()));
+        }
+        let version = u16::from_le_bytes(input.read_array()?);
+        if version != VERSION {
+            return Err(invalid_data(format!("Unsupported binary encoding version {}", version)));
+        }
+        let order = u32::from_le_bytes(input.read_array()?);
+        let training_size = u64::from_le_bytes(input.read_array()?);
+        let created = u64::from_le_bytes(input.read_array()?);
+        let alphabet_len = input.read_u32()?;
+        let alphabet = (0..alphabet_len)
+            .map(|_| input.read_char())
+            .collect::<Result<Vec<char>, io::Error>>()?;
+        let mut settings = Settings::default();
+        for line in input.read_str()?.lines() {
+            settings.parse_encoding_line(line)?;
+        }
+        Ok(Self {
+            version,
+            order,
+            training_size,
+            created,
+            alphabet,
+            settings,
+        })
+    }
+}
+
+/// Whether the file starts with the magic bytes, `MAGIC` for binary encodings.
+pub fn has_magic(path: &str, magic: &[u8]) -> Result<bool, io::Error> {
+    let mut start = vec![0; magic.len()];
+    let read = File::open(path)?.read(&mut start)?;
+    Ok(read == magic.len() && start == magic)
+}
+
+/// Writes the file through a temporary one next to it that is renamed over it at the end, so an encoding
+/// that is memory-mapped, maybe by this process to write it, is never truncated while it's read.
+pub fn replace_file(path: &str, write: impl FnOnce(&mut BufWriter<File>) -> Result<(), io::Error>)
+    -> Result<(), io::Error> {
+    let temporary_path = format!("{}.{}.tmp", path, std::process::id());
+    let result = File::create(&temporary_path)
+        .and_then(|file| {
+            let mut output = BufWriter::new(file);
+            write(&mut output)?;
+            output.flush()?;
+            output.get_ref().sync_all()
+        })
+        .and_then(|()| std::fs::rename(&temporary_path, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temporary_path);
+    }
+    result
+}
+
+/// Seven bits per byte, the lowest first, the high bit is set on all bytes but the last.
+pub fn write_u32(output: &mut Vec<u8>, mut number: u32) {
+    while number >= 0x80 {
+        output.push(number as u8 | 0x80);
+        number >>= 7;
+    }
+    output.push(number as u8);
+}
+
+pub fn write_str(output: &mut Vec<u8>, string: &str) {
+    write_u32(output, string.len() as u32);
+    output.extend_from_slice(string.as_bytes());
+}
+
+/// Appends the CRC-32 of the encoding.
+pub fn append_checksum(output: &mut Vec<u8>) {
+    let checksum = crc32fast::hash(output);
+    output.extend_from_slice(&checksum.to_le_bytes());
+}
+
+/// The encoding without its checksum, if the checksum matches.
+pub fn verify_checksum(input: &[u8]) -> Result<&[u8], io::Error> {
+    if input.len() < 4 {
+        return Err(invalid_data("Binary encoding too short".to_string()));
+    }
+    let (content, checksum) = input.split_at(input.len() - 4);
+    if crc32fast::hash(content) != u32::from_le_bytes(checksum.try_into().unwrap()) {
+        return Err(invalid_data("Checksum mismatch, the binary encoding is corrupt".to_string()));
+    }
+    Ok(content)
+}
+
+pub fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub struct BinaryReader<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BinaryReader<'a> {
+    pub fn new(input: &'a [u8]) -> Self {
+        Self {
+            input,
+            position: 0,
+        }
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.position == self.input.len()
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], io::Error> {
+        let bytes = self.input.get(self.position..self.position + len)
+            .ok_or_else(|| invalid_data(format!("Binary encoding ends early at byte {}", self.position)))?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    pub fn read_array<const N: usize>(&mut self) -> Result<[u8; N], io::Error> {
+        Ok(self.read_bytes(N)?.try_into().unwrap())
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, io::Error> {
+        let mut number: u32 = 0;
+        for shift in (0..32).step_by(7) {
+            let byte = self.read_bytes(1)?[0];
+            number |= ((byte & 0x7f) as u32) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(number);
+            }
+        }
+        Err(invalid_data(format!("Number too long at byte {}", self.position)))
+    }
+
+    pub fn read_char(&mut self) -> Result<char, io::Error> {
+        let code_point = self.read_u32()?;
+        char::from_u32(code_point).ok_or_else(|| invalid_data(format!("Invalid letter {:#x}", code_point)))
+    }
+
+    pub fn read_str(&mut self) -> Result<&'a str, io::Error> {
+        let len = self.read_u32()? as usize;
+        std::str::from_utf8(self.read_bytes(len)?)
+            .map_err(|err| invalid_data(format!("Invalid pattern: {}", err)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern_trees_factory::PatternTreesFactory;
+    use crate::smoothing::Smoothing;
+
+    #[test]
+    fn test_binary_encoding() {
+        let factory = PatternTreesFactory::new(4);
+        let mut pattern_trees = factory.from_password_list("password_list_unicode.txt").unwrap();
+        pattern_trees.set_settings(Settings { smoothing: Smoothing::KneserNey, ..Settings::default() });
+        let path = std::env::temp_dir().join("stochasticpwc_test_binary_encoding.bin");
+        let path = path.to_str().unwrap();
+        pattern_trees.write_binary_encoding(path).unwrap();
+        assert!(has_magic(path, MAGIC).unwrap());
+        assert!(!has_magic("password_list_short.txt", MAGIC).unwrap());
+
+        let encoding = std::fs::read(path).unwrap();
+        let metadata = Metadata::read(&mut BinaryReader::new(verify_checksum(&encoding).unwrap())).unwrap();
+        assert_eq!(metadata.order, 4);
+        assert_eq!(metadata.training_size, 19);
+        assert_eq!(metadata.settings.smoothing, Smoothing::KneserNey);
+        assert!(metadata.alphabet.contains(&'ß') && metadata.alphabet.contains(&'密'));
+
+        let read_pattern_trees = factory.from_encoding(path).unwrap();
+        assert_eq!(read_pattern_trees.settings(), pattern_trees.settings());
+        for password in ["straße", "пароль1", "密码", "qwerty"] {
+            assert_eq!(read_pattern_trees.log_probability(password), pattern_trees.log_probability(password));
+        }
+        let lower_order = PatternTreesFactory::new(2).from_encoding(path).unwrap();
+        assert_eq!(lower_order.metadata().order, 2);
+
+        let mut varints = vec![];
+        for number in [0, 127, 128, 300, u32::MAX] {
+            write_u32(&mut varints, number);
+        }
+        assert_eq!(varints.len(), 1 + 1 + 2 + 2 + 5);
+        let mut input = BinaryReader::new(&varints);
+        for number in [0, 127, 128, 300, u32::MAX] {
+            assert_eq!(input.read_u32().unwrap(), number);
+        }
+        assert!(input.is_at_end());
+
+        let mut corrupt = encoding.clone();
+        corrupt[encoding.len() / 2] ^= 1;
+        std::fs::write(path, corrupt).unwrap();
+        assert_eq!(factory.from_encoding(path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,135 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::keyspace::format_duration;
+
+
+/// How often the guesses check the clock.
+const GUESSES_PER_CLOCK_CHECK: u64 = 256;
+
+/// The lengths of the guesses an attack makes, and how many guesses it may make for how long.
+/// Guesses are counted here and clones share the count, so the threads of an attack share one budget.
+#[derive(Debug, Clone)]
+pub struct Budget {
+    pub min_len: usize,
+    pub max_len: usize,
+    max_guesses: Option<u64>,
+    max_runtime: Option<Duration>,
+    start: Instant,
+    guesses: Arc<AtomicU64>,
+    is_out_of_time: Arc<AtomicBool>,
+}
+
+impl Budget {
+    pub fn new(min_len: usize, max_len: usize, max_guesses: Option<u64>, max_runtime: Option<Duration>) -> Self {
+        Self {
+            min_len,
+            max_len,
+            max_guesses,
+            max_runtime,
+            start: Instant::now(),
+            guesses: Arc::new(AtomicU64::new(0)),
+            is_out_of_time: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Whether a password is long enough to be guessed, the enumerations keep it below `max_len`.
+    pub fn is_long_enough(&self, password: &str) -> bool {
+        self.min_len == 0 || password.chars().nth(self.min_len - 1).is_some()
+    }
+
+    /// Counts a guess and returns its number, or None once the guesses or the time have run out.
+    pub fn next_guess(&self) -> Option<u64> {
+        if self.is_out_of_time.load(Ordering::Relaxed) {
+            return None;
+        }
+        let guess = self.guesses.fetch_add(1, Ordering::Relaxed) + 1;
+        let is_out_of_time = guess.is_multiple_of(GUESSES_PER_CLOCK_CHECK) && self.is_out_of_time();
+        if is_out_of_time || self.max_guesses.is_some_and(|max_guesses| guess > max_guesses) {
+            self.guesses.fetch_sub(1, Ordering::Relaxed);
+            return None;
+        }
+        Some(guess)
+    }
+
+    /// Checks the clock, for work between guesses that takes long.
+    pub fn is_out_of_time(&self) -> bool {
+        if self.max_runtime.is_some_and(|max_runtime| self.start.elapsed() >= max_runtime) {
+            self.is_out_of_time.store(true, Ordering::Relaxed);
+        }
+        self.is_out_of_time.load(Ordering::Relaxed)
+    }
+
+    pub fn guesses(&self) -> u64 {
+        self.guesses.load(Ordering::Relaxed)
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted_limit().is_some()
+    }
+
+    /// The limit that stopped the attack, if one did.
+    pub fn exhausted_limit(&self) -> Option<String> {
+        if self.is_out_of_time.load(Ordering::Relaxed) {
+            return Some(format!("the runtime of {}", format_duration(self.max_runtime.unwrap().as_secs_f64())));
+        }
+        self.max_guesses
+            .filter(|max_guesses| self.guesses() >= *max_guesses)
+            .map(|max_guesses| format!("the limit of {} guesses", max_guesses))
+    }
+}
+
+/// Parses a runtime in seconds, or in minutes, hours or days with the suffix m, h or d.
+pub fn parse_runtime(runtime: &str) -> Result<Duration, String> {
+    let (number, unit_seconds) = match runtime.char_indices().last() {
+        Some((index, 's')) => (&runtime[..index], 1.0),
+        Some((index, 'm')) => (&runtime[..index], 60.0),
+        Some((index, 'h')) => (&runtime[..index], 3600.0),
+        Some((index, 'd')) => (&runtime[..index], 24.0 * 3600.0),
+        _ => (runtime, 1.0),
+    };
+    match number.parse::<f64>() {
+        Ok(number) if number > 0.0 && number.is_finite() => Ok(Duration::from_secs_f64(number * unit_seconds)),
+        _ => Err(format!("{} is not a runtime like 90, 30m, 8h or 2d", runtime)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget() {
+        let budget = Budget::new(2, 8, Some(3), None);
+        assert!(!budget.is_long_enough("a"));
+        assert!(budget.is_long_enough("ab"));
+        let shared_budget = budget.clone();
+        assert_eq!((budget.next_guess(), shared_budget.next_guess(), budget.next_guess()), (Some(1), Some(2), Some(3)));
+        assert_eq!(shared_budget.next_guess(), None);
+        assert_eq!(budget.guesses(), 3);
+        assert_eq!(budget.exhausted_limit(), Some("the limit of 3 guesses".to_string()));
+
+        let budget = Budget::new(0, 8, None, Some(Duration::ZERO));
+        assert!((0..GUESSES_PER_CLOCK_CHECK).any(|_| budget.next_guess().is_none()));
+        assert_eq!(budget.guesses(), GUESSES_PER_CLOCK_CHECK - 1);
+        assert_eq!(budget.next_guess(), None);
+        assert!(budget.exhausted_limit().unwrap().starts_with("the runtime"));
+        assert!(Budget::new(0, 8, None, None).exhausted_limit().is_none());
+    }
+
+    #[test]
+    fn test_parse_runtime() {
+        assert_eq!(parse_runtime("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_runtime("30m"), Ok(Duration::from_secs(1800)));
+        assert_eq!(parse_runtime("1.5h"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_runtime("2d"), Ok(Duration::from_secs(172_800)));
+        for invalid in ["", "m", "-1", "8 hours", "0"] {
+            assert!(parse_runtime(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
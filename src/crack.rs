@@ -1,107 +1,638 @@
-use std::collections::VecDeque;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::rc::Rc;
 use std::sync::mpsc::{Receiver, Sender};
-use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
-use std::time::Duration;
 
-use crate::pattern_trees::PatternTrees;
+use crate::budget::Budget;
+use crate::follower_tables::{FollowerTables, Followers};
+use crate::pattern_tree::END_SYMBOL;
+use crate::pattern_trees::{PatternTrees, ProbableFollower};
+use crate::target::Target;
 
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrackedHash {
+    pub hash: String,
+    pub password: String,
+    pub guesses: u64,
+}
+
+impl CrackedHash {
+    pub fn new(hash: String, password: String, guesses: u64) -> Self {
+        Self {
+            hash,
+            password,
+            guesses,
+        }
+    }
+}
+
+/// A pattern with its base 2 log probability, sums of logs stay exact where products of
+/// many small probabilities would underflow.
 #[derive(Debug)]
 pub struct Word {
     pub pattern: String,
-    pub probability: f64,
+    pub log_probability: f64,
 }
 
 impl Word {
-    pub fn new(pattern: String, probability: f64) -> Self {
+    pub fn new(pattern: String, log_probability: f64) -> Self {
         Self {
             pattern,
-            probability,
+            log_probability,
+        }
+    }
+
+    /// A word ending with the end symbol is a finished password.
+    pub fn is_complete(&self) -> bool {
+        self.pattern.ends_with(END_SYMBOL)
+    }
+
+    /// The password to try for this word. Models without an end symbol try every word.
+    pub fn password(&self, pattern_trees: &PatternTrees) -> Option<&str> {
+        if self.is_complete() {
+            Some(&self.pattern[..self.pattern.len() - END_SYMBOL.len_utf8()])
+        } else if pattern_trees.has_end_symbol() {
+            None
+        } else {
+            Some(&self.pattern)
+        }
+    }
+
+    /// Number of letters, not bytes, of the pattern.
+    pub fn letter_count(&self) -> usize {
+        self.pattern.chars().count()
+    }
+
+    /// Once a word is `max_len` letters long only the end symbol may follow it.
+    pub fn can_grow(&self, pattern_trees: &PatternTrees, max_len: usize) -> bool {
+        !self.is_complete() && (self.letter_count() < max_len || pattern_trees.has_end_symbol())
+    }
+
+    pub fn followers(&self, pattern_trees: &PatternTrees, max_len: usize) -> Vec<Word> {
+        if !self.can_grow(pattern_trees, max_len) {
+            return vec![];
+        }
+        self.followers_among(pattern_trees, &pattern_trees.probable_followers(&self.pattern), max_len)
+    }
+
+    /// The words of the probable followers that fit in `max_len`.
+    pub fn followers_among(&self, pattern_trees: &PatternTrees, probable_followers: &[ProbableFollower],
+        max_len: usize) -> Vec<Word> {
+        let letter_count = self.letter_count();
+        probable_followers
+            .iter()
+            .filter(|probable_follower| letter_count < max_len || probable_follower.letter == END_SYMBOL)
+            .map(|probable_follower| {
+                let mut new_password = self.pattern.clone();
+                new_password.push(probable_follower.letter);
+                Word::new(new_password, self.log_probability + pattern_trees.log_step(probable_follower.probability))
+            })
+            .collect()
+    }
+}
+
+impl PartialEq for Word {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Word {}
+
+impl PartialOrd for Word {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Word {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.log_probability.total_cmp(&other.log_probability)
+    }
+}
+
+fn shrink_heap<T: Ord>(heap: &mut BinaryHeap<T>, keep: usize) {
+    let mut words = std::mem::take(heap).into_vec();
+    words.select_nth_unstable_by(keep, |a, b| b.cmp(a));
+    words.truncate(keep);
+    *heap = BinaryHeap::from(words);
+}
+
+/// Halves a queue that grew beyond `max_queue_size`, warning the first time words are dropped.
+fn limit_queue<T: Ord>(heap: &mut BinaryHeap<T>, max_queue_size: usize, dropped_words: &mut bool) {
+    if heap.len() <= max_queue_size { return; }
+    shrink_heap(heap, max_queue_size / 2);
+    if !*dropped_words {
+        eprintln!("WARNING: Queue exceeded {} words, dropping the less probable ones", max_queue_size);
+        *dropped_words = true;
+    }
+}
+
+/// Yields every password up to `max_len` in strictly descending probability.
+/// If the heap grows beyond `max_queue_size` the less probable half is dropped,
+/// so the order stays exact but words below the dropped ones can be missed.
+pub struct BestFirstWords<'a> {
+    pattern_trees: &'a PatternTrees,
+    heap: BinaryHeap<Word>,
+    max_len: usize,
+    max_queue_size: usize,
+    dropped_words: bool,
+}
+
+impl<'a> BestFirstWords<'a> {
+    pub fn new(pattern_trees: &'a PatternTrees, max_len: usize, max_queue_size: usize) -> Self {
+        let mut heap = BinaryHeap::new();
+        heap.push(Word::new("".to_string(), 0.0));
+        Self {
+            pattern_trees,
+            heap,
+            max_len,
+            max_queue_size,
+            dropped_words: false,
+        }
+    }
+}
+
+impl Iterator for BestFirstWords<'_> {
+    type Item = Word;
+
+    fn next(&mut self) -> Option<Word> {
+        loop {
+            let current = self.heap.pop()?;
+            self.heap.extend(current.followers(self.pattern_trees, self.max_len));
+            limit_queue(&mut self.heap, self.max_queue_size, &mut self.dropped_words);
+            if let Some(password) = current.password(self.pattern_trees) {
+                return Some(Word::new(password.to_string(), current.log_probability));
+            }
+        }
+    }
+}
+
+pub fn crack_best_first(pattern_trees: PatternTrees, budget: &Budget, mut target: Target, max_queue_size: usize) -> Vec<CrackedHash> {
+    let mut cracked_hashes = vec![];
+    for word in BestFirstWords::new(&pattern_trees, budget.max_len, max_queue_size) {
+        if !budget.is_long_enough(&word.pattern) { continue; }
+        let Some(guess) = budget.next_guess() else { break; };
+        if let Some(hash) = target.crack(&word.pattern) {
+            cracked_hashes.push(CrackedHash::new(hash, word.pattern, guess));
+            if target.is_empty() { break; }
+        }
+    }
+    cracked_hashes
+}
+
+/// A word `crack_adaptive` expanded, shared by its queued followers. It keeps its log probability and
+/// its followers under the model of the first `learned_passwords` cracks. After a crack they're brought
+/// up to date once, when the first of its followers is rescored, and the followers are only recomputed
+/// if one of the new passwords changed them.
+struct Expanded {
+    pattern: String,
+    parent: Option<Rc<Expanded>>,
+    scores: RefCell<ExpandedScores>,
+}
+
+struct ExpandedScores {
+    learned_passwords: usize,
+    log_probability: f64,
+    followers: Vec<ProbableFollower>,
+}
+
+impl Expanded {
+    fn update(&self, pattern_trees: &PatternTrees, learned_passwords: &[String]) {
+        let mut scores = self.scores.borrow_mut();
+        if scores.learned_passwords == learned_passwords.len() {
+            return;
+        }
+        if pattern_trees.is_changed_by(&self.pattern, &learned_passwords[scores.learned_passwords..]) {
+            scores.followers = pattern_trees.probable_followers(&self.pattern);
+        }
+        if let Some(parent) = &self.parent {
+            let letter = self.pattern.chars().last().unwrap();
+            scores.log_probability = parent.follower_log_probability(pattern_trees, learned_passwords, letter);
+        }
+        scores.learned_passwords = learned_passwords.len();
+    }
+
+    /// Log probability of the pattern followed by the letter after all the learned passwords.
+    fn follower_log_probability(&self, pattern_trees: &PatternTrees, learned_passwords: &[String], letter: char) -> f64 {
+        self.update(pattern_trees, learned_passwords);
+        let scores = self.scores.borrow();
+        let probability = scores.followers.iter()
+            .find(|probable_follower| probable_follower.letter == letter)
+            .map_or_else(|| pattern_trees.follower_probability(&self.pattern, letter), |probable_follower| probable_follower.probability);
+        scores.log_probability + pattern_trees.log_step(probability)
+    }
+}
+
+/// A queued word of `crack_adaptive` with the word it follows.
+struct AdaptiveWord {
+    word: Word,
+    parent: Option<Rc<Expanded>>,
+}
+
+impl PartialEq for AdaptiveWord {
+    fn eq(&self, other: &Self) -> bool {
+        self.word == other.word
+    }
+}
+
+impl Eq for AdaptiveWord {}
+
+impl PartialOrd for AdaptiveWord {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for AdaptiveWord {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.word.cmp(&other.word)
+    }
+}
+
+/// Recomputes the log probabilities of the queued words after a crack. Siblings share their parent,
+/// so the followers of a parent are only recomputed once, and only if the new password changed them.
+fn rescore_heap(pattern_trees: &PatternTrees, learned_passwords: &[String], heap: &mut BinaryHeap<AdaptiveWord>) {
+    let mut words = std::mem::take(heap).into_vec();
+    for current in words.iter_mut() {
+        if let Some(parent) = &current.parent {
+            let letter = current.word.pattern.chars().last().unwrap();
+            current.word.log_probability = parent.follower_log_probability(pattern_trees, learned_passwords, letter);
         }
     }
+    *heap = BinaryHeap::from(words);
 }
 
-pub fn crack_mp(pattern_trees: PatternTrees, max_len: usize, hash: String, threads: usize) -> Option<String> {
-    let (tx, rx): (Sender<Word>, Receiver<Word>) = mpsc::channel();
-    tx.send(Word::new("".to_string(), 1.0)).unwrap();
+/// Best first like `crack_best_first`, but every cracked password is added `boost` times to the model,
+/// so the passwords of the site that resemble it become more probable, and the queued words are rescored
+/// under the updated model. Models without an end symbol can't learn passwords and are searched without adapting.
+pub fn crack_adaptive(mut pattern_trees: PatternTrees, budget: &Budget, mut target: Target, max_queue_size: usize,
+    boost: u32) -> Vec<CrackedHash> {
+    let mut cracked_hashes = vec![];
+    let mut learned_passwords: Vec<String> = vec![];
+    let mut heap: BinaryHeap<AdaptiveWord> = BinaryHeap::new();
+    heap.push(AdaptiveWord { word: Word::new("".to_string(), 0.0), parent: None });
+    let mut dropped_words = false;
+    while let Some(current) = heap.pop() {
+        if let Some(password) = current.word.password(&pattern_trees).filter(|password| budget.is_long_enough(password)) {
+            let Some(guess) = budget.next_guess() else { break; };
+            if let Some(hash) = target.crack(password) {
+                cracked_hashes.push(CrackedHash::new(hash, password.to_string(), guess));
+                if target.is_empty() { break; }
+                if pattern_trees.has_end_symbol() {
+                    pattern_trees.add_password(password, boost);
+                    learned_passwords.push(password.to_string());
+                    rescore_heap(&pattern_trees, &learned_passwords, &mut heap);
+                }
+            }
+        }
+        if !current.word.can_grow(&pattern_trees, budget.max_len) {
+            continue;
+        }
+        let followers = pattern_trees.probable_followers(&current.word.pattern);
+        let words = current.word.followers_among(&pattern_trees, &followers, budget.max_len);
+        let expanded = Rc::new(Expanded {
+            pattern: current.word.pattern,
+            parent: current.parent,
+            scores: RefCell::new(ExpandedScores {
+                learned_passwords: learned_passwords.len(),
+                log_probability: current.word.log_probability,
+                followers,
+            }),
+        });
+        heap.extend(words.into_iter().map(|word| AdaptiveWord {
+            word,
+            parent: Some(expanded.clone()),
+        }));
+        limit_queue(&mut heap, max_queue_size, &mut dropped_words);
+    }
+    cracked_hashes
+}
 
+enum WorkerMessage {
+    Found(String, u64),
+    Expanded(Vec<Word>),
+}
+
+/// Workers take batches of words from a shared channel, check them against the target and send
+/// back their followers. The frontier stays with the calling thread, which knows the search is
+/// over once it is empty and no batch is in flight, or stops all workers once the target is cracked
+/// or the budget is exhausted.
+pub fn crack_mp(pattern_trees: PatternTrees, budget: &Budget, mut target: Target, threads: usize,
+    batch_size: usize, max_queue_size: usize) -> Vec<CrackedHash> {
     let pattern_trees: Arc<PatternTrees> = Arc::new(pattern_trees);
+    let shared_target: Arc<Target> = Arc::new(target.clone());
+    let stop: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+    let (batch_tx, batch_rx): (Sender<Vec<Word>>, Receiver<Vec<Word>>) = mpsc::channel();
+    let batch_rx: Arc<Mutex<Receiver<Vec<Word>>>> = Arc::new(Mutex::new(batch_rx));
+    let (result_tx, result_rx): (Sender<WorkerMessage>, Receiver<WorkerMessage>) = mpsc::channel();
 
     let mut handles = vec![];
-    let mut thread_txs = vec![];
     for _ in 0..threads {
-        let tx = tx.clone();
-        let (thread_tx, rx): (Sender<Word>, Receiver<Word>) = mpsc::channel();
-        thread_txs.push(thread_tx);
-
         let pattern_trees = Arc::clone(&pattern_trees);
-        let hash = hash.clone();
+        let target = Arc::clone(&shared_target);
+        let stop = Arc::clone(&stop);
+        let budget = budget.clone();
+        let batch_rx = Arc::clone(&batch_rx);
+        let result_tx = result_tx.clone();
 
         let handle = thread::spawn(move || {
             loop {
-                let current = match rx.recv_timeout(Duration::new(0, 50000000)) {
-                    Ok(current) => current,
+                let batch = match batch_rx.lock().unwrap().recv() {
+                    Ok(batch) => batch,
                     Err(_) => return,
                 };
-
-                if current.pattern.starts_with(&hash[0..2]) {println!("{}", current.pattern);}
-                if current.pattern == hash { return; }
-                if current.pattern.len() >= max_len { continue; }
-
-                for probable_follower in pattern_trees.probable_followers(&current.pattern).iter() {
-                    let mut new_password = current.pattern.clone();
-                    new_password.push(probable_follower.letter);
-                    let result = tx.send(Word::new(new_password, current.probability * probable_follower.probability));
-                    if result.is_err() { return; }
+                let mut followers = vec![];
+                for current in batch {
+                    if stop.load(AtomicOrdering::Relaxed) { return; }
+                    if let Some(password) = current.password(&pattern_trees).filter(|password| budget.is_long_enough(password)) {
+                        let Some(guess) = budget.next_guess() else { break; };
+                        if target.is_cracked_by(password)
+                            && result_tx.send(WorkerMessage::Found(password.to_string(), guess)).is_err() {
+                            return;
+                        }
+                    }
+                    followers.extend(current.followers(&pattern_trees, budget.max_len));
                 }
-            };
+                if result_tx.send(WorkerMessage::Expanded(followers)).is_err() { return; }
+            }
         });
         handles.push(handle);
     }
-    let mut index = 0;
-    loop {
-        index = (index + 1) % threads;
-        let word = match rx.recv_timeout(Duration::new(0, 50000000)) {
-            Ok(word) => word,
+    drop(result_tx);
+
+    let mut cracked_hashes = vec![];
+    let mut heap: BinaryHeap<Word> = BinaryHeap::new();
+    heap.push(Word::new("".to_string(), 0.0));
+    let mut batches_in_flight: usize = 0;
+    let mut dropped_words = false;
+    while !target.is_empty() && !budget.is_exhausted() {
+        while batches_in_flight < 2 * threads && !heap.is_empty() {
+            let batch_len = batch_size.min(heap.len());
+            let batch: Vec<Word> = (0..batch_len).map(|_| heap.pop().unwrap()).collect();
+            if batch_tx.send(batch).is_err() { break; }
+            batches_in_flight += 1;
+        }
+        if batches_in_flight == 0 { break; }
+
+        match result_rx.recv() {
+            Ok(WorkerMessage::Found(password, guess)) => {
+                if let Some(hash) = target.crack(&password) {
+                    cracked_hashes.push(CrackedHash::new(hash, password, guess));
+                }
+            }
+            Ok(WorkerMessage::Expanded(followers)) => {
+                batches_in_flight -= 1;
+                heap.extend(followers);
+                limit_queue(&mut heap, max_queue_size, &mut dropped_words);
+            }
             Err(_) => break,
-        };
-        let result = thread_txs[index].send(word);
-        if result.is_err() { break; }
+        }
     }
+    stop.store(true, AtomicOrdering::Relaxed);
+    drop(batch_tx);
     for handle in handles {
         handle.join().unwrap();
     }
-    return Some("string".to_string());
+    for message in result_rx.try_iter() {
+        if let WorkerMessage::Found(password, guess) = message {
+            if let Some(hash) = target.crack(&password) {
+                cracked_hashes.push(CrackedHash::new(hash, password, guess));
+            }
+        }
+    }
+    cracked_hashes
 }
 
-pub fn crack(pattern_trees: PatternTrees, max_len: usize, hash: String) -> Option<String> {
-    let mut queue: VecDeque<Word> = VecDeque::with_capacity(100000);
-    queue.push_back(Word::new("".to_string(), 1.0));
-    let mut probabilities: Vec<f64> = (0..max_len).map(|_| 0.0).collect();
-    while !queue.is_empty() {
-        let current: Word = queue.pop_back().unwrap();
+/// A word of the depth first search whose followers are being visited, from the last to the first
+/// like words pushed onto a stack, which is the order the search has always used.
+struct Frame {
+    followers: Followers,
+    letter_count: usize,
+    pattern_len: usize,
+    log_probability: f64,
+    next_follower: usize,
+}
 
-        if current.pattern.starts_with(&hash[0..2]) {println!("{}", current.pattern);}
-        if current.pattern == hash { return Some(current.pattern); }
-        if current.pattern.len() >= max_len { continue; }
+/// A word about to be tried, the follower of `parent` it was made from or the empty word.
+/// Its context is only looked up if it gets expanded.
+struct Visit {
+    parent: Option<(Followers, usize)>,
+    letter_count: usize,
+    log_probability: f64,
+    is_complete: bool,
+}
 
-        let mut iir_faktor = 0.9;
-        if probabilities[current.pattern.len()] > current.probability { 
-            iir_faktor = 0.7;
-            probabilities[current.pattern.len()] =  iir_faktor * probabilities[current.pattern.len()]
-                + (1.0 - iir_faktor) * current.probability;
+/// Depth first through `FollowerTables`, the pattern is kept in one buffer that is cut back to the
+/// word being expanded. A word is skipped if it's less probable than the running average of the
+/// probabilities of the words of its length, so the search sticks to the most probable branches.
+pub fn crack(pattern_trees: PatternTrees, budget: &Budget, mut target: Target) -> Vec<CrackedHash> {
+    let max_len = budget.max_len;
+    let mut follower_tables = FollowerTables::new(&pattern_trees, max_len);
+    let mut cracked_hashes = vec![];
+    let mut pattern = String::new();
+    let mut frames: Vec<Frame> = Vec::with_capacity(max_len + 2);
+    let mut averages: Vec<f64> = vec![f64::NEG_INFINITY; max_len + 1];
+    let mut next_visit = Some(Visit {
+        parent: None,
+        letter_count: 0,
+        log_probability: 0.0,
+        is_complete: false,
+    });
+    loop {
+        if let Some(visit) = next_visit.take() {
+            let password = if visit.is_complete {
+                Some(&pattern[..pattern.len() - END_SYMBOL.len_utf8()])
+            } else if pattern_trees.has_end_symbol() {
+                None
+            } else {
+                Some(pattern.as_str())
+            };
+            if let Some(password) = password.filter(|password| budget.is_long_enough(password)) {
+                let Some(guess) = budget.next_guess() else { break; };
+                if let Some(hash) = target.crack(password) {
+                    cracked_hashes.push(CrackedHash::new(hash, password.to_string(), guess));
+                    if target.is_empty() { break; }
+                }
+            }
+            let letter_count = visit.letter_count;
+            let can_grow = !visit.is_complete && (letter_count < max_len || pattern_trees.has_end_symbol());
+            if can_grow && !skip_improbable(&mut averages[letter_count], visit.log_probability) {
+                let context = match visit.parent {
+                    Some((parent_followers, index)) => follower_tables.next_context(&parent_followers, index),
+                    None => follower_tables.root(),
+                };
+                let followers = follower_tables.followers(context, letter_count);
+                if letter_count < max_len {
+                    frames.push(Frame {
+                        followers,
+                        letter_count,
+                        pattern_len: pattern.len(),
+                        log_probability: visit.log_probability,
+                        next_follower: followers.count,
+                    });
+                } else if let Some(index) = follower_tables.end_follower(&followers) {
+                    let (letter, log_step) = follower_tables.follower(&followers, index);
+                    pattern.push(letter);
+                    next_visit = Some(Visit {
+                        parent: Some((followers, index)),
+                        letter_count: letter_count + 1,
+                        log_probability: visit.log_probability + log_step,
+                        is_complete: true,
+                    });
+                    continue;
+                }
+            }
+        }
+
+        let Some(frame) = frames.last_mut() else { break; };
+        if frame.next_follower == 0 {
+            frames.pop();
             continue;
         }
-        probabilities[current.pattern.len()] =  iir_faktor * probabilities[current.pattern.len()]
-            + (1.0 - iir_faktor) * current.probability;
-        if probabilities[current.pattern.len()] > current.probability { continue; }
+        frame.next_follower -= 1;
+        let (letter, log_step) = follower_tables.follower(&frame.followers, frame.next_follower);
+        pattern.truncate(frame.pattern_len);
+        pattern.push(letter);
+        next_visit = Some(Visit {
+            parent: Some((frame.followers, frame.next_follower)),
+            letter_count: frame.letter_count + 1,
+            log_probability: frame.log_probability + log_step,
+            is_complete: letter == END_SYMBOL,
+        });
+    }
+    cracked_hashes
+}
+
+/// Updates the running average of the probabilities of words of one length and tells whether
+/// the word is too improbable to expand, the average moves faster towards words below it.
+/// The average is kept as a base 2 log like the probabilities, so it doesn't underflow either.
+fn skip_improbable(log_average: &mut f64, log_probability: f64) -> bool {
+    if *log_average > log_probability {
+        *log_average = log_mix(*log_average, log_probability, 0.3);
+        return true;
+    }
+    *log_average = log_mix(*log_average, log_probability, 0.1);
+    *log_average > log_probability
+}
+
+/// log2((1 - weight) * 2^a + weight * 2^b) without leaving log space.
+fn log_mix(a: f64, b: f64, weight: f64) -> f64 {
+    if a == f64::NEG_INFINITY && b == f64::NEG_INFINITY {
+        a
+    } else if a >= b {
+        a + (1.0 - weight + weight * (b - a).exp2()).log2()
+    } else {
+        b + ((1.0 - weight) * (a - b).exp2() + weight).log2()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern_trees_factory::PatternTreesFactory;
+    use crate::settings::Settings;
+    use crate::smoothing::Smoothing;
+    use crate::target::HashType;
+
+    #[test]
+    fn test_best_first_words_descending() {
+        let pattern_trees = PatternTreesFactory::new(3).from_password_list("password_list_short.txt").unwrap();
+        let words: Vec<Word> = BestFirstWords::new(&pattern_trees, 4, 1_000_000).take(2000).collect();
+        assert_eq!(words.len(), 2000);
+        assert!(words.iter().all(|word| word.letter_count() <= 4 && !word.pattern.contains(END_SYMBOL)));
+        for pair in words.windows(2) {
+            assert!(pair[0].log_probability >= pair[1].log_probability);
+        }
+    }
+
+    #[test]
+    fn test_best_first_words_bounded_queue() {
+        let pattern_trees = PatternTreesFactory::new(3).from_password_list("password_list_short.txt").unwrap();
+        let mut words = BestFirstWords::new(&pattern_trees, 6, 100);
+        let mut previous = f64::INFINITY;
+        for word in words.by_ref().take(5000) {
+            assert!(word.log_probability <= previous);
+            previous = word.log_probability;
+        }
+        assert!(words.heap.len() <= 100);
+    }
 
-        for probable_follower in pattern_trees.probable_followers(&current.pattern).iter() {
-            let mut new_password = current.pattern.clone();
-            new_password.push(probable_follower.letter);
-            queue.push_back(Word::new(new_password, current.probability * probable_follower.probability));
+    #[test]
+    fn test_long_words_stay_ordered() {
+        let mut pattern_trees = PatternTreesFactory::new(3).from_password_list("password_list_short.txt").unwrap();
+        pattern_trees.set_settings(Settings { smoothing: Smoothing::Additive, ..Settings::default() });
+        let mut word = Word::new("".to_string(), 0.0);
+        for _ in 0..400 {
+            word = word.followers(&pattern_trees, 1000).into_iter()
+                .filter(|follower| !follower.is_complete())
+                .max()
+                .unwrap();
         }
+        assert!(word.log_probability < -1100.0 && word.log_probability.is_finite(), "{}", word.log_probability);
+        let followers = word.followers(&pattern_trees, 1000);
+        for (follower, probable_follower) in followers.iter().zip(pattern_trees.probable_followers(&word.pattern)) {
+            assert_eq!(follower.log_probability, word.log_probability + probable_follower.probability.log2());
+        }
+        assert!(followers.windows(2).all(|pair| pair[0].log_probability >= pair[1].log_probability));
+
+        pattern_trees.set_settings(Settings { level_width: Some(0.5), ..pattern_trees.settings().clone() });
+        assert_eq!(pattern_trees.log_step(0.25), -2.0);
+        assert_eq!(pattern_trees.log_step(0.3), -1.5);
+        for word in BestFirstWords::new(&pattern_trees, 4, 1_000_000).take(500) {
+            assert_eq!((word.log_probability * 2.0).fract(), 0.0);
+        }
+    }
+
+    #[test]
+    fn test_crack_mp() {
+        let pattern_trees = PatternTreesFactory::new(3).from_password_list("password_list_short.txt").unwrap();
+        let target = Target::new(HashType::Md5, "1a1dc91c907325c69271ddf0c944bc72").unwrap();
+        let cracked_hashes = crack_mp(pattern_trees.clone(), &Budget::new(0, 4, None, None), target, 4, 100, 1_000_000);
+        assert_eq!(cracked_hashes.len(), 1);
+        assert_eq!(cracked_hashes[0].password, "pass");
+
+        let target = Target::new(HashType::Plain, "not in the search space").unwrap();
+        assert!(crack_mp(pattern_trees, &Budget::new(0, 2, None, None), target, 4, 100, 1_000_000).is_empty());
+    }
+
+    #[test]
+    fn test_crack_mp_keeps_last_cracks() {
+        let pattern_trees = PatternTreesFactory::new(3).from_password_list("password_list_short.txt").unwrap();
+        let path = std::env::temp_dir().join("stochasticpwc_test_crack_mp_keeps_last_cracks.txt");
+        let passwords: Vec<String> = BestFirstWords::new(&pattern_trees, 6, 1_000_000)
+            .map(|word| word.pattern)
+            .take(5000)
+            .collect();
+        std::fs::write(&path, passwords.join("\n")).unwrap();
+        for max_guesses in (200..600).step_by(20) {
+            let target = Target::from_hash_list(HashType::Plain, path.to_str().unwrap()).unwrap();
+            let budget = Budget::new(1, 6, Some(max_guesses), None);
+            let cracked_hashes = crack_mp(pattern_trees.clone(), &budget, target, 8, 10, 1_000_000);
+            assert_eq!(cracked_hashes.len() as u64, budget.guesses());
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_crack_adaptive() {
+        let pattern_trees = PatternTreesFactory::new(3).from_password_list("password_list_short.txt").unwrap();
+        let path = std::env::temp_dir().join("stochasticpwc_test_crack_adaptive.txt");
+        std::fs::write(&path, "qwerty1\nmonkey1\n").unwrap();
+        let target = Target::from_hash_list(HashType::Plain, path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        // The "y1" end learned from qwerty1 moves monkey1, a word of another branch, ahead of the queued words.
+        let monkey1_rank = BestFirstWords::new(&pattern_trees, 7, 1_000_000).position(|word| word.pattern == "monkey1").unwrap();
+        let cracked_hashes = crack_adaptive(pattern_trees, &Budget::new(0, 7, None, None), target, 1_000_000, 100);
+        assert_eq!(cracked_hashes.len(), 2);
+        assert_eq!(cracked_hashes[0].password, "qwerty1");
+        assert_eq!(cracked_hashes[1].password, "monkey1");
+        assert!(cracked_hashes[1].guesses - cracked_hashes[0].guesses < (monkey1_rank as u64 - cracked_hashes[0].guesses) / 4);
     }
-    return None;
 }
@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+
+use crate::pattern_tree::{START_SYMBOL, END_SYMBOL};
+use crate::pattern_trees::PatternTrees;
+
+
+/// Marks a transition that hasn't been looked up yet.
+const UNKNOWN_CONTEXT: u32 = u32::MAX;
+/// The level of a letter that can't follow.
+const NO_LEVEL: u32 = u32::MAX;
+
+/// A follower with the log step and level of its conditional probability and the context it leads to.
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    log_step: f64,
+    letter: char,
+    level: u32,
+    next_context: u32,
+}
+
+/// The pruned followers of one context are the entries from `start` on, most probable first,
+/// with the end symbol last if it isn't among the most probable ones.
+#[derive(Debug, Clone, Copy)]
+struct FollowerTable {
+    start: usize,
+    /// Number of followers before the appended end symbol.
+    most_probable: usize,
+    end_index: Option<usize>,
+}
+
+/// The followers of one pattern: the first `cut_off` followers of the table of its context and the end symbol.
+#[derive(Debug, Clone, Copy)]
+pub struct Followers {
+    context: usize,
+    cut_off: usize,
+    end_index: Option<usize>,
+    pub count: usize,
+}
+
+/// The follower distribution only depends on the last `order - 1` letters of the pattern with the
+/// start symbol in front, so every such context gets an id and a table computed once, the first time
+/// the search reaches it. Tables are linked by the context each follower leads to, so walking down
+/// the search tree neither looks up strings nor computes probabilities again.
+/// A table keeps as many followers as the pruning allows for the shortest pattern with that context.
+/// The entries of all tables lie one after the other in a single arena.
+pub struct FollowerTables<'a> {
+    pattern_trees: &'a PatternTrees,
+    max_len: usize,
+    context_ids: HashMap<String, u32>,
+    contexts: Vec<String>,
+    tables: Vec<FollowerTable>,
+    entries: Vec<Entry>,
+    key: String,
+}
+
+impl<'a> FollowerTables<'a> {
+    pub fn new(pattern_trees: &'a PatternTrees, max_len: usize) -> Self {
+        let mut follower_tables = Self {
+            pattern_trees,
+            max_len,
+            context_ids: HashMap::new(),
+            contexts: vec![],
+            tables: vec![],
+            entries: vec![],
+            key: String::new(),
+        };
+        if pattern_trees.has_end_symbol() && pattern_trees.order() > 1 {
+            follower_tables.key.push(START_SYMBOL);
+        }
+        follower_tables.context_id();
+        follower_tables
+    }
+
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    /// Id of the context of the empty pattern.
+    pub fn root(&self) -> usize {
+        0
+    }
+
+    /// The followers of a pattern of `letter_count` letters with this context.
+    pub fn followers(&self, context: usize, letter_count: usize) -> Followers {
+        let table = &self.tables[context];
+        let cut_off = self.pattern_trees.settings().pruning.followers_for_pattern_length(letter_count)
+            .min(table.most_probable);
+        let end_index = table.end_index.map(|end_index| end_index.min(cut_off));
+        Followers {
+            context,
+            cut_off,
+            end_index,
+            count: if end_index == Some(cut_off) { cut_off + 1 } else { cut_off },
+        }
+    }
+
+    /// Index of the end symbol among the followers, if the model has one.
+    pub fn end_follower(&self, followers: &Followers) -> Option<usize> {
+        followers.end_index
+    }
+
+    /// Letter and `PatternTrees::log_step` of a follower, in the order of `probable_followers`.
+    pub fn follower(&self, followers: &Followers, index: usize) -> (char, f64) {
+        let entry = &self.entries[self.entry_index(followers, index)];
+        (entry.letter, entry.log_step)
+    }
+
+    /// `PatternTrees::level` of a follower, the levels of the followers never decrease.
+    pub fn level(&self, followers: &Followers, index: usize) -> Option<u32> {
+        let level = self.entries[self.entry_index(followers, index)].level;
+        (level != NO_LEVEL).then_some(level)
+    }
+
+    /// The context reached by appending the follower, its table is computed if it's new.
+    pub fn next_context(&mut self, followers: &Followers, index: usize) -> usize {
+        let entry_index = self.entry_index(followers, index);
+        let Entry { letter, next_context, .. } = self.entries[entry_index];
+        if next_context != UNKNOWN_CONTEXT {
+            return next_context as usize;
+        }
+        self.key.clear();
+        self.key.push_str(&self.contexts[followers.context]);
+        self.key.push(letter);
+        if self.key.chars().count() >= self.pattern_trees.order() {
+            let first_len = self.key.chars().next().unwrap().len_utf8();
+            self.key.drain(..first_len);
+        }
+        let next_context = self.context_id();
+        self.entries[entry_index].next_context = next_context as u32;
+        next_context
+    }
+
+    fn entry_index(&self, followers: &Followers, index: usize) -> usize {
+        let table = &self.tables[followers.context];
+        table.start + if index < followers.cut_off { index } else { table.end_index.unwrap() }
+    }
+
+    /// Id of the context in `key`, the last `order - 1` letters of a pattern with the start symbol
+    /// in front if the model has one.
+    fn context_id(&mut self) -> usize {
+        if let Some(id) = self.context_ids.get(self.key.as_str()) {
+            return *id as usize;
+        }
+        let table = self.add_follower_table();
+        let id = self.tables.len();
+        self.context_ids.insert(self.key.clone(), id as u32);
+        self.contexts.push(self.key.clone());
+        self.tables.push(table);
+        id
+    }
+
+    /// A context without the start symbol has at least as many letters as it is long,
+    /// since it lost the start symbol by being cut to `order - 1` letters.
+    /// An end symbol in the last place is counted as appended, which cuts the followers the same way.
+    fn add_follower_table(&mut self) -> FollowerTable {
+        let pattern = self.key.strip_prefix(START_SYMBOL).unwrap_or(&self.key);
+        let min_letter_count = pattern.chars().count();
+        let pruning = &self.pattern_trees.settings().pruning;
+        let most_probable = (min_letter_count..=self.max_len.max(min_letter_count))
+            .map(|length| pruning.followers_for_pattern_length(length))
+            .max()
+            .unwrap();
+        let probable_followers = self.pattern_trees.pruned_followers(pattern, most_probable);
+        let end_index = probable_followers.iter().position(|follower| follower.letter == END_SYMBOL);
+        let most_probable = if end_index == Some(probable_followers.len() - 1) {
+            probable_followers.len() - 1
+        } else {
+            probable_followers.len()
+        };
+        let start = self.entries.len();
+        self.entries.extend(probable_followers.iter().map(|follower| Entry {
+            log_step: self.pattern_trees.log_step(follower.probability),
+            letter: follower.letter,
+            level: self.pattern_trees.level(follower.probability).unwrap_or(NO_LEVEL),
+            next_context: UNKNOWN_CONTEXT,
+        }));
+        FollowerTable {
+            start,
+            most_probable,
+            end_index,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern_trees_factory::PatternTreesFactory;
+    use crate::pruning::Pruning;
+    use crate::settings::Settings;
+
+    #[test]
+    fn test_follower_tables_match_probable_followers() {
+        let prunings = [Pruning::Schedule, Pruning::TopK(3), Pruning::Nucleus(0.8), Pruning::PerPosition(vec![2, 5, 1])];
+        for (path, pruning) in ["password_list_short.txt", "password_list_unicode.txt"].into_iter()
+            .flat_map(|path| prunings.iter().map(move |pruning| (path, pruning))) {
+            let mut pattern_trees = PatternTreesFactory::new(3).from_password_list(path).unwrap();
+            pattern_trees.set_settings(Settings { pruning: pruning.clone(), ..pattern_trees.settings().clone() });
+            let mut follower_tables = FollowerTables::new(&pattern_trees, 6);
+            let mut patterns = vec![(String::new(), follower_tables.root())];
+            while let Some((pattern, context)) = patterns.pop() {
+                let letter_count = pattern.chars().count();
+                let probable_followers = pattern_trees.probable_followers(&pattern);
+                let followers = follower_tables.followers(context, letter_count);
+                assert_eq!(followers.count, probable_followers.len());
+                for (index, probable_follower) in probable_followers.iter().enumerate() {
+                    let (letter, log_step) = follower_tables.follower(&followers, index);
+                    assert_eq!(letter, probable_follower.letter);
+                    assert_eq!(log_step, probable_follower.probability.log2());
+                    if letter == END_SYMBOL {
+                        assert_eq!(follower_tables.end_follower(&followers), Some(index));
+                    } else if letter_count < 3 && index < 4 {
+                        let next_context = follower_tables.next_context(&followers, index);
+                        patterns.push((format!("{}{}", pattern, letter), next_context));
+                    }
+                }
+            }
+            assert!(follower_tables.tables.len() > 1, "{}", pruning);
+        }
+    }
+}
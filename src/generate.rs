@@ -0,0 +1,36 @@
+use std::io::{self, Write};
+
+use crate::budget::Budget;
+use crate::crack::BestFirstWords;
+use crate::pattern_trees::PatternTrees;
+
+
+/// Writes guesses in descending probability, one per line, until the budget is exhausted.
+/// The budget counts the written guesses.
+pub fn generate(pattern_trees: &PatternTrees, output: &mut impl Write, budget: &Budget, max_queue_size: usize)
+    -> Result<(), io::Error> {
+    for word in BestFirstWords::new(pattern_trees, budget.max_len, max_queue_size) {
+        if !budget.is_long_enough(&word.pattern) { continue; }
+        if budget.next_guess().is_none() { break; }
+        writeln!(output, "{}", word.pattern)?;
+    }
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern_trees_factory::PatternTreesFactory;
+
+    #[test]
+    fn test_generate() {
+        let pattern_trees = PatternTreesFactory::new(3).from_password_list("password_list_short.txt").unwrap();
+        let mut output: Vec<u8> = vec![];
+        let budget = Budget::new(2, 4, Some(500), None);
+        generate(&pattern_trees, &mut output, &budget, 1_000_000).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(budget.guesses(), 500);
+        assert_eq!(output.lines().count(), 500);
+        assert!(output.lines().all(|line| (2..=4).contains(&line.len())));
+    }
+}
@@ -0,0 +1,155 @@
+use rand::Rng;
+
+use crate::pattern_trees::PatternTrees;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GuessNumberEstimate {
+    pub guess_number: f64,
+    pub lower: f64,
+    pub upper: f64,
+}
+
+/// Sampled base 2 log probabilities, most probable first, with the running sums
+/// of 1/p and 1/p² needed for the estimate and its variance. Samples outside the
+/// guesses count towards `sample_count` without a log probability.
+#[derive(Debug, Clone)]
+struct Samples {
+    log_probabilities: Vec<f64>,
+    sample_count: usize,
+    inverse_sums: Vec<f64>,
+    squared_inverse_sums: Vec<f64>,
+}
+
+impl Samples {
+    fn new(mut log_probabilities: Vec<f64>, sample_count: usize) -> Self {
+        log_probabilities.sort_unstable_by(|a, b| b.total_cmp(a));
+        let mut inverse_sums = Vec::with_capacity(log_probabilities.len() + 1);
+        let mut squared_inverse_sums = Vec::with_capacity(log_probabilities.len() + 1);
+        inverse_sums.push(0.0);
+        squared_inverse_sums.push(0.0);
+        for log_probability in log_probabilities.iter() {
+            inverse_sums.push(inverse_sums.last().unwrap() + (-log_probability).exp2());
+            squared_inverse_sums.push(squared_inverse_sums.last().unwrap() + (-2.0 * log_probability).exp2());
+        }
+        Self {
+            log_probabilities,
+            sample_count,
+            inverse_sums,
+            squared_inverse_sums,
+        }
+    }
+
+    /// Estimated number of passwords more probable than `log_probability` and the variance of that estimate.
+    fn rank(&self, log_probability: f64) -> (f64, f64) {
+        let more_probable = self.log_probabilities.partition_point(|sample| *sample > log_probability);
+        let sample_count = self.sample_count as f64;
+        let mean = self.inverse_sums[more_probable] / sample_count;
+        let second_moment = self.squared_inverse_sums[more_probable] / sample_count;
+        (mean, (second_moment - mean * mean).max(0.0) / sample_count)
+    }
+}
+
+/// Monte Carlo guess number estimation after Dell'Amico and Filippone: the number of passwords
+/// more probable than p is estimated by summing 1/p_i over the n samples with p_i > p and dividing by n.
+/// Models without an end symbol have no distribution over lengths, so every length up to `max_len`
+/// is sampled separately and the ranks are added up. Otherwise a sample that hasn't ended after `max_len` letters
+/// isn't a guess, it's counted but adds nothing. The estimate is for the model without the follower pruning.
+#[derive(Debug, Clone)]
+pub struct GuessNumberEstimator {
+    sample_sets: Vec<Samples>,
+    max_len: usize,
+}
+
+impl GuessNumberEstimator {
+    pub fn new(pattern_trees: &PatternTrees, rng: &mut impl Rng, samples: usize, max_len: usize) -> Self {
+        let sample_sets = if pattern_trees.has_end_symbol() {
+            vec![Samples::new((0..samples)
+                .map(|_| pattern_trees.sample(rng, max_len + 1, None))
+                .filter(|(password, _)| password.chars().count() <= max_len)
+                .map(|(_, log_probability)| log_probability)
+                .collect(), samples)]
+        } else {
+            (1..=max_len)
+                .map(|length| Samples::new((0..samples)
+                    .map(|_| pattern_trees.sample(rng, length, None).1)
+                    .collect(), samples))
+                .collect()
+        };
+        Self {
+            sample_sets,
+            max_len,
+        }
+    }
+
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    /// Estimated guess number of a password with the given base 2 log probability and its 95% confidence interval.
+    pub fn estimate(&self, log_probability: f64) -> GuessNumberEstimate {
+        let (rank, variance) = self.sample_sets.iter()
+            .map(|samples| samples.rank(log_probability))
+            .fold((0.0, 0.0), |(rank, variance), (length_rank, length_variance)| (rank + length_rank, variance + length_variance));
+        let half_width = 1.96 * variance.sqrt();
+        GuessNumberEstimate {
+            guess_number: rank + 1.0,
+            lower: (rank - half_width).max(0.0) + 1.0,
+            upper: rank + half_width + 1.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use std::collections::BinaryHeap;
+
+    use super::*;
+    use crate::crack::Word;
+    use crate::pattern_tree::END_SYMBOL;
+    use crate::pattern_trees_factory::PatternTreesFactory;
+
+    /// Exact best first enumeration of the passwords up to `max_len` over the whole follower distribution,
+    /// without the pruning.
+    fn exact_log_probabilities(pattern_trees: &PatternTrees, count: usize, max_len: usize) -> Vec<f64> {
+        let mut heap: BinaryHeap<Word> = BinaryHeap::new();
+        heap.push(Word::new("".to_string(), 0.0));
+        let mut log_probabilities = vec![];
+        while log_probabilities.len() < count {
+            let current = heap.pop().unwrap();
+            if current.is_complete() {
+                log_probabilities.push(current.log_probability);
+                continue;
+            }
+            let is_full = current.letter_count() == max_len;
+            for probable_follower in pattern_trees.follower_distribution(&current.pattern) {
+                if probable_follower.probability <= 0.0 || (is_full && probable_follower.letter != END_SYMBOL) { continue; }
+                let mut new_password = current.pattern.clone();
+                new_password.push(probable_follower.letter);
+                heap.push(Word::new(new_password, current.log_probability + probable_follower.probability.log2()));
+            }
+        }
+        log_probabilities
+    }
+
+    #[test]
+    fn test_estimate_matches_exact_rank() {
+        let pattern_trees = PatternTreesFactory::new(3).from_password_list("password_list_short.txt").unwrap();
+        for max_len in [40, 4] {
+            let mut rng = ChaCha8Rng::seed_from_u64(7);
+            let estimator = GuessNumberEstimator::new(&pattern_trees, &mut rng, 20000, max_len);
+            let log_probabilities = exact_log_probabilities(&pattern_trees, 201, max_len);
+
+            for exact_rank in [10, 50, 200] {
+                let estimate = estimator.estimate(log_probabilities[exact_rank]);
+                let exact_guess_number = (exact_rank + 1) as f64;
+                assert!(estimate.lower <= estimate.guess_number && estimate.guess_number <= estimate.upper);
+                assert!((estimate.guess_number - exact_guess_number).abs() / exact_guess_number < 0.2,
+                    "estimated {:?} with max length {} for exact guess number {}", estimate, max_len, exact_guess_number);
+            }
+        }
+    }
+}
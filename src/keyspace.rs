@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::follower_tables::FollowerTables;
+use crate::pattern_tree::END_SYMBOL;
+use crate::pattern_trees::PatternTrees;
+use crate::target::HashType;
+
+
+/// Number of words of at most `max_len` letters the pruning lets the depth first enumeration reach,
+/// it tries fewer as it skips improbable words. The words below a pattern only depend on its context
+/// and length, so each pair is counted once.
+pub fn count_candidates(pattern_trees: &PatternTrees, max_len: usize) -> u64 {
+    let mut follower_tables = FollowerTables::new(pattern_trees, max_len);
+    let mut counts = HashMap::new();
+    let root = follower_tables.root();
+    count_words(&mut follower_tables, &mut counts, root, 0)
+}
+
+fn count_words(follower_tables: &mut FollowerTables, counts: &mut HashMap<(usize, usize), u64>, context: usize,
+    letter_count: usize) -> u64 {
+    if let Some(count) = counts.get(&(context, letter_count)) {
+        return *count;
+    }
+    let followers = follower_tables.followers(context, letter_count);
+    let mut count: u64 = if follower_tables.end_follower(&followers).is_some() { 0 } else { 1 };
+    for index in 0..followers.count {
+        let (letter, _) = follower_tables.follower(&followers, index);
+        if letter == END_SYMBOL {
+            count = count.saturating_add(1);
+        } else if letter_count < follower_tables.max_len() {
+            let next_context = follower_tables.next_context(&followers, index);
+            count = count.saturating_add(count_words(follower_tables, counts, next_context, letter_count + 1));
+        }
+    }
+    counts.insert((context, letter_count), count);
+    count
+}
+
+/// Guesses per second of one thread, measured by walking the pruned search tree depth first
+/// and hashing every word for about the given time.
+pub fn measure_guess_rate(pattern_trees: &PatternTrees, max_len: usize, hash_type: HashType, duration: Duration) -> f64 {
+    let mut follower_tables = FollowerTables::new(pattern_trees, max_len);
+    let start = Instant::now();
+    let mut guesses: u64 = 0;
+    let mut pattern = String::new();
+    let root = follower_tables.root();
+    hash_words(&mut follower_tables, &mut pattern, root, 0, &mut |password| {
+        std::hint::black_box(hash_type.digest(password));
+        guesses += 1;
+        !guesses.is_multiple_of(1024) || start.elapsed() < duration
+    });
+    guesses as f64 / start.elapsed().as_secs_f64()
+}
+
+/// Calls `hash` with every word below the pattern until it returns false.
+fn hash_words(follower_tables: &mut FollowerTables, pattern: &mut String, context: usize, letter_count: usize,
+    hash: &mut impl FnMut(&str) -> bool) -> bool {
+    let followers = follower_tables.followers(context, letter_count);
+    if follower_tables.end_follower(&followers).is_none() && !hash(pattern) {
+        return false;
+    }
+    let pattern_len = pattern.len();
+    for index in 0..followers.count {
+        let (letter, _) = follower_tables.follower(&followers, index);
+        let keep_going = if letter == END_SYMBOL {
+            hash(pattern)
+        } else if letter_count < follower_tables.max_len() {
+            pattern.push(letter);
+            let next_context = follower_tables.next_context(&followers, index);
+            let keep_going = hash_words(follower_tables, pattern, next_context, letter_count + 1, hash);
+            pattern.truncate(pattern_len);
+            keep_going
+        } else {
+            true
+        };
+        if !keep_going {
+            return false;
+        }
+    }
+    true
+}
+
+/// Seconds as the largest unit that keeps the number at least 1.
+pub fn format_duration(seconds: f64) -> String {
+    let units = [("years", 365.25 * 24.0 * 3600.0), ("days", 24.0 * 3600.0), ("hours", 3600.0), ("minutes", 60.0)];
+    match units.iter().find(|(_, unit_seconds)| seconds >= *unit_seconds) {
+        Some((unit, unit_seconds)) => format!("{:.2} {}", seconds / unit_seconds, unit),
+        None => format!("{:.2} seconds", seconds),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern_trees_factory::PatternTreesFactory;
+    use crate::pruning::Pruning;
+    use crate::settings::Settings;
+
+    fn count_probable_words(pattern_trees: &PatternTrees, pattern: &str, max_len: usize) -> u64 {
+        let letter_count = pattern.chars().count();
+        let mut count = if pattern_trees.has_end_symbol() { 0 } else { 1 };
+        for follower in pattern_trees.probable_followers(pattern) {
+            if follower.letter == END_SYMBOL {
+                count += 1;
+            } else if letter_count < max_len {
+                count += count_probable_words(pattern_trees, &format!("{}{}", pattern, follower.letter), max_len);
+            }
+        }
+        count
+    }
+
+    #[test]
+    fn test_count_candidates() {
+        let mut pattern_trees = PatternTreesFactory::new(3).from_password_list("password_list_short.txt").unwrap();
+        for pruning in [Pruning::TopK(4), Pruning::Nucleus(0.5), Pruning::PerPosition(vec![8, 4, 2])] {
+            pattern_trees.set_settings(Settings { pruning, ..pattern_trees.settings().clone() });
+            assert_eq!(count_candidates(&pattern_trees, 4), count_probable_words(&pattern_trees, "", 4));
+        }
+        assert_eq!(format_duration(90.0), "1.50 minutes");
+        assert_eq!(format_duration(0.5), "0.50 seconds");
+    }
+}
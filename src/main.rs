@@ -1,40 +1,310 @@
-use clap::Parser;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::time::Duration;
 
+use clap::{Parser, Subcommand, ValueEnum};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+mod binary_encoding;
+mod budget;
 mod pattern_tree;
 mod pattern_trees;
 mod pattern_trees_factory;
+mod pruning;
 mod crack;
+mod follower_tables;
+mod generate;
+mod guess_number;
+mod mapped_encoding;
+mod keyspace;
+mod normalization;
+mod omen;
+mod settings;
+mod smoothing;
+mod target;
+mod threshold;
+mod text_encoding;
 
-use crate::pattern_trees_factory::PatternTreesFactory;
+use crate::budget::{parse_runtime, Budget};
+use crate::pattern_trees_factory::{ListFormat, PatternTreesFactory};
 use crate::pattern_trees::PatternTrees;
-use crate::crack::{crack, crack_mp};
+use crate::crack::{crack, crack_adaptive, crack_best_first, crack_mp};
+use crate::omen::crack_omen;
+use crate::generate::generate;
+use crate::guess_number::GuessNumberEstimator;
+use crate::keyspace::{count_candidates, format_duration, measure_guess_rate};
+use crate::normalization::Normalization;
+use crate::pruning::Pruning;
+use crate::settings::Settings;
+use crate::smoothing::Smoothing;
+use crate::target::{HashType, Target};
+use crate::threshold::crack_threshold;
 
 
-//TODO: multithreading batch sizes and with channels for stopping and getting the result
-//TODO: target, that supports lists and actual hashes
 //TODO: test stochastic pwc against traditional methods
 
 
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+enum Enumeration {
+    /// Depth first with pruning of improbable words, fast but only roughly ordered
+    #[default]
+    DepthFirst,
+    /// Exactly in descending probability, needs more memory
+    BestFirst,
+    /// Best first on one thread, learning from every password cracked during the run
+    Adaptive,
+    /// OMEN style, every guess of level 0, then 1 and so on, needs little memory and splits over the threads
+    Omen,
+    /// Every guess more probable than --threshold, lowering the bound by --threshold-step each round,
+    /// the number of guesses of a round is known before it starts
+    Threshold,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+enum EncodingFormat {
+    Text,
+    /// Compact, with a header and a checksum, loads much faster
+    #[default]
+    Binary,
+    /// Larger, queried in place without reading it into memory
+    Mapped,
+}
+
+impl EncodingFormat {
+    fn of_encoding(path: &str) -> Result<Self, io::Error> {
+        if binary_encoding::has_magic(path, mapped_encoding::MAGIC)? {
+            Ok(Self::Mapped)
+        } else if binary_encoding::has_magic(path, binary_encoding::MAGIC)? {
+            Ok(Self::Binary)
+        } else {
+            Ok(Self::Text)
+        }
+    }
+
+    fn write(&self, pattern_trees: &PatternTrees, path: &str) -> Result<(), io::Error> {
+        match self {
+            Self::Text => pattern_trees.write_encoding(path),
+            Self::Binary => pattern_trees.write_binary_encoding(path),
+            Self::Mapped => pattern_trees.write_mapped_encoding(path),
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Write guesses in descending probability to stdout, one per line, for hashcat or John
+    Generate {
+        /// Stop after this many guesses
+        #[arg(long, visible_alias = "limit")]
+        max_guesses: Option<u64>,
+
+        /// Stop after this long, in seconds or with the suffix m, h or d
+        #[arg(long, value_parser = parse_runtime)]
+        max_runtime: Option<Duration>,
+
+        #[arg(long, default_value_t = 1)]
+        min_len: usize,
+
+        #[arg(long, default_value_t = 8)]
+        max_len: usize,
+    },
+    /// Read passwords from stdin and print their base 2 log probability and their probability,
+    /// the negated log probability is the strength in bits
+    Score,
+    /// Read passwords from stdin and print a Monte Carlo estimate of their guess number
+    /// with its 95% confidence interval
+    Estimate {
+        /// Number of sampled passwords per length
+        #[arg(long, default_value_t = 10000)]
+        samples: usize,
+
+        #[arg(long)]
+        seed: Option<u64>,
+
+        #[arg(long, default_value_t = 12)]
+        max_len: usize,
+    },
+    /// Print passwords drawn at random from the model, one per line
+    Sample {
+        #[arg(long, default_value_t = 100)]
+        count: usize,
+
+        /// The same seed always gives the same passwords
+        #[arg(long)]
+        seed: Option<u64>,
+
+        #[arg(long, default_value_t = 8)]
+        max_len: usize,
+
+        /// Below 1 favours probable letters, above 1 flattens the distribution
+        #[arg(long, value_parser = parse_positive)]
+        temperature: Option<f64>,
+    },
+    /// Add the counts of further encodings to the loaded model and write the result as an encoding
+    Merge {
+        /// Encodings to add, the model loaded with --encoding or --list-passwords is the base
+        #[arg(required = true)]
+        encodings: Vec<String>,
+
+        /// One weight per encoding its counts are multiplied with, 1 by default
+        #[arg(long, value_parser = parse_positive)]
+        weights: Vec<f64>,
+
+        /// Written in the format of the loaded encoding, as text for a model trained with --list-passwords
+        #[arg(short, long)]
+        output: String,
+    },
+    /// Write the loaded model as a text, binary or mapped encoding, encodings are read in any format
+    Convert {
+        #[arg(short, long)]
+        output: String,
+
+        #[arg(long, value_enum, default_value_t = EncodingFormat::Binary)]
+        format: EncodingFormat,
+    },
+    /// Print how many candidates the pruning leaves up to a length and how long trying them takes
+    Keyspace {
+        #[arg(long, default_value_t = 8)]
+        max_len: usize,
+
+        /// Guesses per second, measured with the hash type on one thread by default
+        #[arg(long, value_parser = parse_positive)]
+        guess_rate: Option<f64>,
+    },
+    /// Add the passwords of further lists to the loaded model, for example cracked ones, and write it as an encoding
+    Update {
+        /// Lists in the format given by --list-format
+        #[arg(required = true)]
+        password_lists: Vec<String>,
+
+        /// Defaults to overwriting the loaded encoding, the encoding is written in the format of the loaded one
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+}
+
+fn parse_positive(number: &str) -> Result<f64, String> {
+    match number.parse::<f64>() {
+        Ok(parsed) if parsed > 0.0 => Ok(parsed),
+        _ => Err(format!("{} is not a positive number", number)),
+    }
+}
+
+fn parse_fraction(number: &str) -> Result<f64, String> {
+    match number.parse::<f64>() {
+        Ok(parsed) if parsed > 0.0 && parsed < 1.0 => Ok(parsed),
+        _ => Err(format!("{} is not a number between 0 and 1", number)),
+    }
+}
+
+fn parse_above_one(number: &str) -> Result<f64, String> {
+    match number.parse::<f64>() {
+        Ok(parsed) if parsed > 1.0 => Ok(parsed),
+        _ => Err(format!("{} is not a number above 1", number)),
+    }
+}
+
 /// Program to crack passwords with probability
 #[derive(Parser, Debug, Default)]
 #[command(version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
 
     #[arg(short, long, default_value_t = 1)]
     threads: usize,
 
-    #[arg(short, long, default_value_t = 5)]
+    #[arg(short, long, global = true, default_value_t = 5)]
     count_pattern_trees: usize,
 
-    #[arg(short, long)]
+    #[arg(long, value_enum, default_value_t = Enumeration::DepthFirst)]
+    enumeration: Enumeration,
+
+    /// How many times the adaptive enumeration adds a cracked password to the model
+    #[arg(long, default_value_t = 100)]
+    boost: u32,
+
+    /// Lowest probability of a guess of the threshold enumeration
+    #[arg(long, value_parser = parse_positive, default_value_t = 1e-10)]
+    threshold: f64,
+
+    /// Factor the threshold enumeration lowers its bound by each round
+    #[arg(long, value_parser = parse_above_one, default_value_t = 10.0)]
+    threshold_step: f64,
+
+    /// Number of words a thread checks and expands at once
+    #[arg(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..), default_value_t = 1000)]
+    batch_size: usize,
+
+    /// Maximum number of words queued by the best first and multithreaded enumeration
+    #[arg(long, global = true, default_value_t = 5_000_000)]
+    max_queue_size: usize,
+
+    #[arg(short, long, global = true)]
     encoding: Option<String>,
 
+    /// Hex digest to crack, or the password itself for the plain hash type
     #[arg(short, long)]
     password_hash: Option<String>,
 
-    #[arg(short, long)]
+    /// File with one hex digest per line, or one password per line for the plain hash type
+    #[arg(long)]
+    hash_list: Option<String>,
+
+    #[arg(long, global = true, value_enum, default_value_t = HashType::Plain)]
+    hash_type: HashType,
+
+    /// Minimum length of guessed passwords
+    #[arg(long, default_value_t = 0)]
+    min_len: usize,
+
+    /// Maximum length of guessed passwords
+    #[arg(long, default_value_t = 8)]
+    max_len: usize,
+
+    /// Stop the attack after this many guesses
+    #[arg(long)]
+    max_guesses: Option<u64>,
+
+    /// Stop the attack after this long, in seconds or with the suffix m, h or d
+    #[arg(long, value_parser = parse_runtime)]
+    max_runtime: Option<Duration>,
+
+    #[arg(short, long, global = true)]
     list_passwords: Option<String>,
 
+    /// How the lines of the password list are weighted
+    #[arg(long, global = true, value_enum, default_value_t = ListFormat::Plain)]
+    list_format: ListFormat,
+
+    /// Overrides the smoothing saved in the encoding, models from password lists default to none
+    #[arg(long, global = true, value_enum)]
+    smoothing: Option<Smoothing>,
+
+    #[arg(long, global = true)]
+    additive_alpha: Option<f64>,
+
+    /// How many pattern trees below the longest fitting one are mixed, overrides the encoding
+    #[arg(long, global = true, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    backoff_window: Option<usize>,
+
+    /// Share of the password list, between 0 and 1, held out to learn the interpolation weights of the pattern trees on, not with Kneser-Ney smoothing
+    #[arg(long, global = true, value_parser = parse_fraction)]
+    held_out_fraction: Option<f64>,
+
+    /// Unicode normalization of the trained and scored passwords, overrides the encoding
+    #[arg(long, global = true, value_enum, visible_alias = "charset")]
+    normalization: Option<Normalization>,
+
+    /// Rank guesses by OMEN style levels of this many bits instead of exact log probabilities, overrides the encoding
+    #[arg(long, global = true, value_parser = parse_positive)]
+    level_width: Option<f64>,
+
+    /// Followers expanded per pattern: schedule (60 / (length + 1) + 1), top-k:<count>, nucleus:<mass>
+    /// or per-position:<count>,<count>,..., overrides the encoding
+    #[arg(long, global = true, value_parser = Pruning::parse)]
+    pruning: Option<Pruning>,
+
     #[arg(long)]
     path_write_encoding: Option<String>,
 
@@ -42,47 +312,265 @@ struct Args {
     path_write_probabilities: Option<String>,
 }
 
+impl Args {
+    fn has_settings(&self) -> bool {
+        self.smoothing.is_some() || self.additive_alpha.is_some() || self.backoff_window.is_some()
+            || self.normalization.is_some() || self.level_width.is_some() || self.pruning.is_some()
+    }
+
+    fn settings(&self, mut settings: Settings) -> Settings {
+        settings.smoothing = self.smoothing.unwrap_or(settings.smoothing);
+        settings.additive_alpha = self.additive_alpha.unwrap_or(settings.additive_alpha);
+        settings.backoff_window = self.backoff_window.unwrap_or(settings.backoff_window);
+        settings.normalization = self.normalization.unwrap_or(settings.normalization);
+        settings.level_width = self.level_width.or(settings.level_width);
+        settings.pruning = self.pruning.clone().unwrap_or(settings.pruning);
+        settings
+    }
+}
+
 
 fn main() {
     let args: Args = Args::parse();
-    let pattern_trees_factory = PatternTreesFactory::new(args.count_pattern_trees);
-    let pattern_trees: PatternTrees;
+    if matches!(args.enumeration, Enumeration::Adaptive) && args.threads > 1 {
+        eprintln!("ERROR: The adaptive enumeration runs on one thread, leave out --threads");
+        return;
+    }
+    if args.held_out_fraction.is_some() && args.smoothing == Some(Smoothing::KneserNey) {
+        eprintln!("ERROR: Kneser-Ney smoothing doesn't use learned interpolation weights, leave out --held-out-fraction");
+        return;
+    }
+    let mut pattern_trees_factory = PatternTreesFactory::new(args.count_pattern_trees);
+    pattern_trees_factory.settings = args.settings(Settings::default());
+    pattern_trees_factory.held_out_fraction = args.held_out_fraction;
+    pattern_trees_factory.list_format = args.list_format;
+    let mut pattern_trees: PatternTrees;
 
-    if let Some(encoding) = args.encoding {
-        println!("INFO: Building pattern tree from encoding...");
+    if let Some(encoding) = args.encoding.clone() {
+        eprintln!("INFO: Building pattern tree from encoding...");
         pattern_trees = pattern_trees_factory.pattern_trees_with_error_handling(
             PatternTreesFactory::from_encoding, "an encoding".to_string(),  encoding);
-    } else if let Some(password_list) = args.list_passwords {
-        println!("INFO: Building pattern tree from password list...");
+        if args.has_settings() {
+            pattern_trees.set_settings(args.settings(pattern_trees.settings().clone()));
+        }
+    } else if let Some(password_list) = args.list_passwords.clone() {
+        eprintln!("INFO: Building pattern tree from password list...");
         pattern_trees = pattern_trees_factory.pattern_trees_with_error_handling(
             PatternTreesFactory::from_password_list, "a list of passwords".to_string(), password_list);
     } else {
         pattern_trees = pattern_trees_factory.pattern_trees_with_error_handling(
             PatternTreesFactory::from_encoding, "an encoding".to_string(),  "pattern_tree_encoding.txt".to_string());
+        if args.has_settings() {
+            pattern_trees.set_settings(args.settings(pattern_trees.settings().clone()));
+        }
+    }
+    eprintln!("INFO: Built pattern trees");
+    if pattern_trees.settings().smoothing == Smoothing::KneserNey && pattern_trees.settings().interpolation_weights.is_some() {
+        eprintln!("WARNING: Kneser-Ney smoothing ignores the learned interpolation weights of the encoding");
     }
-    println!("INFO: Built pattern trees");
     
     if let Some(path_write_probabilities) = args.path_write_probabilities {
-        println!("INFO: Writing probabilities...");
+        eprintln!("INFO: Writing probabilities...");
         pattern_trees.write_with_error_handling(PatternTrees::write_probability_distribution, 
                                                 "the probability distribution of the counts of patterns".to_string(), path_write_probabilities);
-        println!("INFO: Wrote probabilities");
+        eprintln!("INFO: Wrote probabilities");
     }
     if let Some(path_write_encoding) = args.path_write_encoding {
-        println!("INFO: Writing encoding...");
+        eprintln!("INFO: Writing encoding...");
         pattern_trees.write_with_error_handling(PatternTrees::write_encoding, 
                                                 "the encoding for the pattern trees".to_string(), path_write_encoding);
-        println!("INFO: Wrote encoding");
+        eprintln!("INFO: Wrote encoding");
+    }
+    if let Some(Command::Generate { max_guesses, max_runtime, min_len, max_len }) = args.command {
+        eprintln!("INFO: Generating...");
+        let budget = Budget::new(min_len, max_len, max_guesses, max_runtime);
+        let mut output = BufWriter::new(io::stdout().lock());
+        match generate(&pattern_trees, &mut output, &budget, args.max_queue_size) {
+            Ok(()) => {
+                if let Some(limit) = budget.exhausted_limit() {
+                    eprintln!("INFO: Stopped by {}", limit);
+                }
+                eprintln!("DONE: Generated {} guesses in {}", budget.guesses(),
+                    format_duration(budget.elapsed().as_secs_f64()));
+            }
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => {},
+            Err(err) => eprintln!("ERROR: {}", err),
+        }
+        return;
+    }
+    if let Some(Command::Score) = args.command {
+        let mut output = BufWriter::new(io::stdout().lock());
+        for line in io::stdin().lock().lines() {
+            let password = match line {
+                Ok(password) => pattern_trees.normalize(&password),
+                Err(err) => {
+                    eprintln!("ERROR: {}", err);
+                    return;
+                }
+            };
+            let log_probability = pattern_trees.log_probability(&password);
+            let probability = pattern_trees.probability(&password);
+            if writeln!(output, "{:.4}\t{:e}\t{}", log_probability, probability, password).is_err() { return; }
+        }
+        return;
+    }
+    if let Some(Command::Estimate { samples, seed, max_len }) = args.command {
+        eprintln!("INFO: Sampling...");
+        let mut rng = match seed {
+            Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+            None => ChaCha8Rng::from_entropy(),
+        };
+        let estimator = GuessNumberEstimator::new(&pattern_trees, &mut rng, samples, max_len);
+        eprintln!("INFO: Sampled");
+        let mut output = BufWriter::new(io::stdout().lock());
+        for line in io::stdin().lock().lines() {
+            let password = match line {
+                Ok(password) => pattern_trees.normalize(&password),
+                Err(err) => {
+                    eprintln!("ERROR: {}", err);
+                    return;
+                }
+            };
+            let result = if password.chars().count() > estimator.max_len() {
+                writeln!(output, "-\t-\t-\t{}", password)
+            } else {
+                let estimate = estimator.estimate(pattern_trees.log_probability(&password));
+                writeln!(output, "{:.0}\t{:.0}\t{:.0}\t{}", estimate.guess_number, estimate.lower, estimate.upper, password)
+            };
+            if result.is_err() { return; }
+        }
+        return;
+    }
+    if let Some(Command::Sample { count, seed, max_len, temperature }) = args.command {
+        let mut rng = match seed {
+            Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+            None => ChaCha8Rng::from_entropy(),
+        };
+        let mut output = BufWriter::new(io::stdout().lock());
+        for _ in 0..count {
+            let (password, _) = pattern_trees.sample(&mut rng, max_len, temperature);
+            if writeln!(output, "{}", password).is_err() { return; }
+        }
+        return;
+    }
+    if let Some(Command::Keyspace { max_len, guess_rate }) = args.command {
+        eprintln!("INFO: Counting candidates...");
+        let candidates = count_candidates(&pattern_trees, max_len);
+        let (guess_rate, source) = match guess_rate {
+            Some(guess_rate) => (guess_rate, "given".to_string()),
+            None => {
+                eprintln!("INFO: Measuring the guess rate...");
+                let guess_rate = measure_guess_rate(&pattern_trees, max_len, args.hash_type, Duration::from_secs(2));
+                (guess_rate, format!("measured with {:?} on one thread", args.hash_type))
+            }
+        };
+        println!("Pruning: {}", pattern_trees.settings().pruning);
+        println!("Candidates up to {} letters: {}", max_len, candidates);
+        println!("Guess rate: {:.0} per second, {}", guess_rate, source);
+        println!("Estimated time: {}", format_duration(candidates as f64 / guess_rate));
+        return;
     }
-    if let Some(password_hash) = args.password_hash {
-        println!("INFO: Attacking...");
-        let optional_password = if args.threads == 1 {crack(pattern_trees, password_hash.len(), password_hash)}
-            else {crack_mp(pattern_trees, password_hash.len(), password_hash, args.threads)};
-        if let Some(password) = optional_password {
-            println!("DONE: Found {}", password);
+    if let Some(Command::Merge { encodings, weights, output }) = &args.command {
+        if !weights.is_empty() && weights.len() != encodings.len() {
+            eprintln!("ERROR: Got {} weights for {} encodings", weights.len(), encodings.len());
+            return;
+        }
+        let format = match args.encoding.as_deref().map(EncodingFormat::of_encoding).transpose() {
+            Ok(format) => format.unwrap_or(EncodingFormat::Text),
+            Err(err) => {
+                eprintln!("ERROR: {}", err);
+                return;
+            }
+        };
+        for (index, encoding) in encodings.iter().enumerate() {
+            eprintln!("INFO: Merging {}...", encoding);
+            let result = pattern_trees_factory.from_encoding(encoding)
+                .and_then(|other| pattern_trees.merge(&other, weights.get(index).copied().unwrap_or(1.0)));
+            if let Err(err) = result {
+                eprintln!("ERROR: {}: {}", encoding, err);
+                return;
+            }
+        }
+        match format.write(&pattern_trees, output) {
+            Ok(()) => eprintln!("DONE: Merged {} encodings into {}", encodings.len(), output),
+            Err(err) => eprintln!("ERROR: {}", err),
+        }
+        return;
+    }
+    if let Some(Command::Convert { output, format }) = &args.command {
+        match format.write(&pattern_trees, output) {
+            Ok(()) => {
+                let metadata = pattern_trees.metadata();
+                eprintln!("DONE: Wrote {} with {} pattern trees, {} letters and {} training passwords",
+                    output, metadata.order, metadata.alphabet.len(), metadata.training_size);
+            }
+            Err(err) => eprintln!("ERROR: {}", err),
+        }
+        return;
+    }
+    if let Some(Command::Update { password_lists, output }) = &args.command {
+        let Some(output) = output.as_ref().or(args.encoding.as_ref()) else {
+            eprintln!("ERROR: Give an --output for the updated encoding");
+            return;
+        };
+        let format = match args.encoding.as_deref().map(EncodingFormat::of_encoding).transpose() {
+            Ok(format) => format.unwrap_or(EncodingFormat::Text),
+            Err(err) => {
+                eprintln!("ERROR: {}", err);
+                return;
+            }
+        };
+        for password_list in password_lists.iter() {
+            eprintln!("INFO: Adding {}...", password_list);
+            pattern_trees = match pattern_trees_factory.add_password_list(pattern_trees, password_list) {
+                Ok(pattern_trees) => pattern_trees,
+                Err(err) => {
+                    eprintln!("ERROR: {}: {}", password_list, err);
+                    return;
+                }
+            };
+        }
+        match format.write(&pattern_trees, output) {
+            Ok(()) => eprintln!("DONE: Added {} password lists and wrote {}", password_lists.len(), output),
+            Err(err) => eprintln!("ERROR: {}", err),
+        }
+        return;
+    }
+    let target = if let Some(hash_list) = &args.hash_list {
+        Some(Target::from_hash_list(args.hash_type, hash_list))
+    } else {
+        args.password_hash.as_ref().map(|password_hash| Target::new(args.hash_type, password_hash))
+    };
+    if let Some(target) = target {
+        let target = match target {
+            Ok(target) => target,
+            Err(err) => {
+                eprintln!("ERROR: {}", err);
+                return;
+            }
+        };
+        let budget = Budget::new(args.min_len, args.max_len, args.max_guesses, args.max_runtime);
+        let hashes: Vec<String> = target.remaining_hashes().cloned().collect();
+        eprintln!("INFO: Attacking {} hashes...", target.len());
+        let cracked_hashes = match args.enumeration {
+            Enumeration::Omen => crack_omen(pattern_trees, &budget, target, args.threads),
+            Enumeration::Threshold => crack_threshold(pattern_trees, &budget, target, args.threshold, args.threshold_step),
+            _ if args.threads > 1 => crack_mp(pattern_trees, &budget, target, args.threads,
+                args.batch_size, args.max_queue_size),
+            Enumeration::BestFirst => crack_best_first(pattern_trees, &budget, target, args.max_queue_size),
+            Enumeration::Adaptive => crack_adaptive(pattern_trees, &budget, target, args.max_queue_size, args.boost),
+            Enumeration::DepthFirst => crack(pattern_trees, &budget, target),
+        };
+        for cracked_hash in cracked_hashes.iter() {
+            println!("FOUND: {}:{} after {} guesses", cracked_hash.hash, cracked_hash.password, cracked_hash.guesses);
+        }
+        for hash in hashes.iter().filter(|hash| !cracked_hashes.iter().any(|cracked_hash| &cracked_hash.hash == *hash)) {
+            println!("NOT FOUND: {}", hash);
         }
-        else {
-            println!("DONE: Nothing Found");
+        if let Some(limit) = budget.exhausted_limit().filter(|_| cracked_hashes.len() < hashes.len()) {
+            println!("STOPPED: Ran out of {} before the search space was covered", limit);
         }
+        println!("DONE: Cracked {} of {} hashes with {} guesses in {}", cracked_hashes.len(), hashes.len(),
+            budget.guesses(), format_duration(budget.elapsed().as_secs_f64()));
     }
 }
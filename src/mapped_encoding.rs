@@ -0,0 +1,323 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::slice::ChunksExact;
+
+use memmap2::Mmap;
+
+use crate::binary_encoding::{self, invalid_data, BinaryReader};
+use crate::pattern_tree::{Follower, PatternTree};
+use crate::settings::Settings;
+use crate::smoothing::SmoothingTables;
+
+
+pub const MAGIC: &[u8; 4] = b"SPWM";
+pub const VERSION: u32 = 1;
+
+/// Offset and length of the pattern, offset and number of the followers.
+const ENTRY_LEN: usize = 8 + 4 + 8 + 4;
+/// Index of the letter in the alphabet and count.
+const FOLLOWER_LEN: usize = 4 + 4;
+
+#[derive(Debug, Clone, Copy)]
+struct Table {
+    offset: usize,
+    pattern_count: usize,
+}
+
+/// An encoding laid out to be queried where it lies, so processes mapping the same file share one copy.
+/// All numbers are little endian. The header holds the magic, the version, the order, the alphabet as
+/// letters, the settings as their encoding lines, and the Good-Turing counts and Kneser-Ney discount
+/// of every pattern tree, followed by the offset and pattern count of every table: first the pattern
+/// trees, then the continuation trees. A table is a sorted array of fixed size entries, one per pattern,
+/// pointing to the bytes of the pattern and its followers, so a pattern is found by binary search.
+/// The smoothing tables of all smoothings are stored, so any smoothing can be queried in place.
+#[derive(Debug)]
+pub struct MappedEncoding {
+    mmap: Mmap,
+    pub order: usize,
+    pub alphabet: Vec<char>,
+    pub settings: Settings,
+    pub good_turing_counts: Vec<BTreeMap<u64, f64>>,
+    pub discounts: Vec<f64>,
+    tables: Vec<Table>,
+    stored_order: usize,
+}
+
+impl MappedEncoding {
+    /// Only the header is read, at most `max_order` pattern trees are used.
+    /// The file must not be changed while it is mapped.
+    pub fn open(path: &str, max_order: usize) -> Result<Self, io::Error> {
+        let file = File::open(path)?;
+        // Safety: the mapping is only read. The writers of this program never write an encoding in place,
+        // they rename a new file over it, so the mapped file keeps its contents. Other programs must not
+        // modify the file while it's open.
+        let mmap = unsafe { Mmap::map(&file)? };
+        let mut input = BinaryReader::new(&mmap);
+        if input.read_bytes(MAGIC.len())? != MAGIC {
+            return Err(invalid_data("Not a mapped encoding".to_string()));
+        }
+        let version = u32::from_le_bytes(input.read_array()?);
+        if version != VERSION {
+            return Err(invalid_data(format!("Unsupported mapped encoding version {}", version)));
+        }
+        let stored_order = u32::from_le_bytes(input.read_array()?) as usize;
+        if stored_order == 0 {
+            return Err(invalid_data("Mapped encoding without pattern trees".to_string()));
+        }
+        let alphabet_len = u32::from_le_bytes(input.read_array()?);
+        let alphabet = (0..alphabet_len)
+            .map(|_| {
+                let code_point = u32::from_le_bytes(input.read_array()?);
+                char::from_u32(code_point).ok_or_else(|| invalid_data(format!("Invalid letter {:#x}", code_point)))
+            })
+            .collect::<Result<Vec<char>, io::Error>>()?;
+        let settings_len = u32::from_le_bytes(input.read_array()?) as usize;
+        let settings_lines = std::str::from_utf8(input.read_bytes(settings_len)?)
+            .map_err(|err| invalid_data(format!("Invalid settings: {}", err)))?;
+        let mut settings = Settings::default();
+        for line in settings_lines.lines() {
+            settings.parse_encoding_line(line)?;
+        }
+        let mut good_turing_counts = Vec::with_capacity(stored_order);
+        let mut discounts = Vec::with_capacity(stored_order);
+        for _ in 0..stored_order {
+            let count_len = u32::from_le_bytes(input.read_array()?);
+            let mut counts = BTreeMap::new();
+            for _ in 0..count_len {
+                let count = u64::from_le_bytes(input.read_array()?);
+                counts.insert(count, f64::from_le_bytes(input.read_array()?));
+            }
+            good_turing_counts.push(counts);
+            discounts.push(f64::from_le_bytes(input.read_array()?));
+        }
+        let mut tables = Vec::with_capacity(2 * stored_order - 1);
+        for _ in 0..2 * stored_order - 1 {
+            let offset = u64::from_le_bytes(input.read_array()?) as usize;
+            let pattern_count = u64::from_le_bytes(input.read_array()?) as usize;
+            if pattern_count.checked_mul(ENTRY_LEN).and_then(|len| len.checked_add(offset)).is_none_or(|end| end > mmap.len()) {
+                return Err(invalid_data("Table beyond the end of the mapped encoding".to_string()));
+            }
+            tables.push(Table { offset, pattern_count });
+        }
+        let mapped_encoding = Self {
+            mmap,
+            order: stored_order.min(max_order),
+            alphabet,
+            settings,
+            good_turing_counts,
+            discounts,
+            tables,
+            stored_order,
+        };
+        if mapped_encoding.followers(0, "").is_none() {
+            return Err(invalid_data("Mapped encoding without letter frequencies".to_string()));
+        }
+        Ok(mapped_encoding)
+    }
+
+    fn bytes(&self, offset: u64, len: usize) -> Option<&[u8]> {
+        let offset = usize::try_from(offset).ok()?;
+        self.mmap.get(offset..offset.checked_add(len)?)
+    }
+
+    /// Offset and length of the pattern and offset and number of the followers of an entry.
+    fn entry(&self, table: Table, index: usize) -> (u64, usize, u64, usize) {
+        let entry = &self.mmap[table.offset + index * ENTRY_LEN..table.offset + (index + 1) * ENTRY_LEN];
+        (
+            u64::from_le_bytes(entry[0..8].try_into().unwrap()),
+            u32::from_le_bytes(entry[8..12].try_into().unwrap()) as usize,
+            u64::from_le_bytes(entry[12..20].try_into().unwrap()),
+            u32::from_le_bytes(entry[20..24].try_into().unwrap()) as usize,
+        )
+    }
+
+    fn table_followers(&self, table: Table, pattern: &str) -> Option<MappedFollowers<'_>> {
+        let (mut low, mut high) = (0, table.pattern_count);
+        while low < high {
+            let middle = (low + high) / 2;
+            let (pattern_offset, pattern_len, followers_offset, follower_count) = self.entry(table, middle);
+            match self.bytes(pattern_offset, pattern_len)?.cmp(pattern.as_bytes()) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => return Some(MappedFollowers {
+                    bytes: self.bytes(followers_offset, follower_count.checked_mul(FOLLOWER_LEN)?)?,
+                    alphabet_len: self.alphabet.len(),
+                }),
+            }
+        }
+        None
+    }
+
+    pub fn followers(&self, pattern_tree_index: usize, pattern: &str) -> Option<MappedFollowers<'_>> {
+        self.table_followers(*self.tables.get(pattern_tree_index)?, pattern)
+    }
+
+    /// The followers in the continuation tree of the order.
+    pub fn continuation_followers(&self, pattern_tree_index: usize, pattern: &str) -> Option<MappedFollowers<'_>> {
+        self.table_followers(*self.tables.get(self.stored_order + pattern_tree_index)?, pattern)
+    }
+
+    /// Reads the pattern trees into memory, as needed to change the counts.
+    pub fn pattern_trees(&self) -> Vec<PatternTree> {
+        self.tables[..self.order].iter()
+            .map(|table| {
+                let mut pattern_tree = PatternTree::new();
+                for index in 0..table.pattern_count {
+                    let (pattern_offset, pattern_len, _, _) = self.entry(*table, index);
+                    let Some(pattern) = self.bytes(pattern_offset, pattern_len) else { continue };
+                    let pattern = String::from_utf8_lossy(pattern);
+                    let Some(followers) = self.table_followers(*table, &pattern) else { continue };
+                    let followers: Vec<Follower> = followers.iter()
+                        .map(|(letter_index, count)| Follower::new(count, self.alphabet[letter_index]))
+                        .collect();
+                    pattern_tree.total_follower_count += followers.iter().map(|follower| follower.count as u64).sum::<u64>();
+                    pattern_tree.pattern_tree_implementation.insert(pattern.into_owned(), followers);
+                }
+                pattern_tree
+            })
+            .collect()
+    }
+
+    /// `smoothing_tables` has to hold the tables of every smoothing.
+    pub fn write(path: &str, pattern_trees: &[PatternTree], alphabet: &[char], settings: &Settings,
+        smoothing_tables: &SmoothingTables) -> Result<(), io::Error> {
+        let letter_indices: std::collections::HashMap<char, u32> = alphabet.iter()
+            .enumerate()
+            .map(|(index, letter)| (*letter, index as u32))
+            .collect();
+        let mut output: Vec<u8> = vec![];
+        output.extend_from_slice(MAGIC);
+        output.extend_from_slice(&VERSION.to_le_bytes());
+        output.extend_from_slice(&(pattern_trees.len() as u32).to_le_bytes());
+        output.extend_from_slice(&(alphabet.len() as u32).to_le_bytes());
+        for letter in alphabet.iter() {
+            output.extend_from_slice(&(*letter as u32).to_le_bytes());
+        }
+        let settings_lines = settings.encoding_lines().join("\n");
+        output.extend_from_slice(&(settings_lines.len() as u32).to_le_bytes());
+        output.extend_from_slice(settings_lines.as_bytes());
+        for (good_turing_counts, discount) in smoothing_tables.good_turing_counts.iter().zip(smoothing_tables.discounts.iter()) {
+            output.extend_from_slice(&(good_turing_counts.len() as u32).to_le_bytes());
+            for (count, good_turing_count) in good_turing_counts.iter() {
+                output.extend_from_slice(&count.to_le_bytes());
+                output.extend_from_slice(&good_turing_count.to_le_bytes());
+            }
+            output.extend_from_slice(&discount.to_le_bytes());
+        }
+
+        let tables: Vec<&PatternTree> = pattern_trees.iter().chain(smoothing_tables.continuation_trees.iter()).collect();
+        let mut directory_position = output.len();
+        output.resize(output.len() + tables.len() * 16, 0);
+        for table in tables {
+            let entries_position = output.len();
+            let pattern_count = table.pattern_tree_implementation.len();
+            output[directory_position..directory_position + 8].copy_from_slice(&(entries_position as u64).to_le_bytes());
+            output[directory_position + 8..directory_position + 16].copy_from_slice(&(pattern_count as u64).to_le_bytes());
+            directory_position += 16;
+            output.resize(entries_position + pattern_count * ENTRY_LEN, 0);
+            for (index, (pattern, followers)) in table.pattern_tree_implementation.iter().enumerate() {
+                let pattern_offset = output.len() as u64;
+                output.extend_from_slice(pattern.as_bytes());
+                let followers_offset = output.len() as u64;
+                for follower in followers.iter() {
+                    let letter_index = letter_indices.get(&follower.letter)
+                        .ok_or_else(|| invalid_data(format!("Letter {:?} isn't in the alphabet", follower.letter)))?;
+                    output.extend_from_slice(&letter_index.to_le_bytes());
+                    output.extend_from_slice(&follower.count.to_le_bytes());
+                }
+                let entry_position = entries_position + index * ENTRY_LEN;
+                let entry = &mut output[entry_position..entry_position + ENTRY_LEN];
+                entry[0..8].copy_from_slice(&pattern_offset.to_le_bytes());
+                entry[8..12].copy_from_slice(&(pattern.len() as u32).to_le_bytes());
+                entry[12..20].copy_from_slice(&followers_offset.to_le_bytes());
+                entry[20..24].copy_from_slice(&(followers.len() as u32).to_le_bytes());
+            }
+        }
+        binary_encoding::replace_file(path, |file| file.write_all(&output))
+    }
+}
+
+/// The followers of a pattern as they lie in the mapped encoding.
+#[derive(Debug, Clone, Copy)]
+pub struct MappedFollowers<'a> {
+    bytes: &'a [u8],
+    alphabet_len: usize,
+}
+
+impl<'a> MappedFollowers<'a> {
+    pub fn len(&self) -> usize {
+        self.bytes.len() / FOLLOWER_LEN
+    }
+
+    /// Letter indices and counts, letters beyond the alphabet of a damaged encoding are skipped.
+    pub fn iter(&self) -> MappedFollowerCounts<'a> {
+        MappedFollowerCounts {
+            chunks: self.bytes.chunks_exact(FOLLOWER_LEN),
+            alphabet_len: self.alphabet_len,
+        }
+    }
+}
+
+pub struct MappedFollowerCounts<'a> {
+    chunks: ChunksExact<'a, u8>,
+    alphabet_len: usize,
+}
+
+impl Iterator for MappedFollowerCounts<'_> {
+    type Item = (usize, u32);
+
+    fn next(&mut self) -> Option<(usize, u32)> {
+        loop {
+            let chunk = self.chunks.next()?;
+            let letter_index = u32::from_le_bytes(chunk[0..4].try_into().unwrap()) as usize;
+            if letter_index < self.alphabet_len {
+                return Some((letter_index, u32::from_le_bytes(chunk[4..8].try_into().unwrap())));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern_trees::PatternTrees;
+    use crate::pattern_trees_factory::PatternTreesFactory;
+    use crate::smoothing::Smoothing;
+
+    #[test]
+    fn test_mapped_encoding() {
+        let path = std::env::temp_dir().join("stochasticpwc_test_mapped_encoding.map");
+        let path = path.to_str().unwrap();
+        let mut pattern_trees = PatternTreesFactory::new(4).from_password_list("password_list_unicode.txt").unwrap();
+        pattern_trees.write_mapped_encoding(path).unwrap();
+        let mut mapped = PatternTreesFactory::new(4).from_encoding(path).unwrap();
+
+        for smoothing in [Smoothing::None, Smoothing::Additive, Smoothing::GoodTuring, Smoothing::KneserNey] {
+            let settings = Settings { smoothing, ..pattern_trees.settings().clone() };
+            pattern_trees.set_settings(settings.clone());
+            mapped.set_settings(settings);
+            for password in ["пароль", "straße1", "pass密", "xyz"] {
+                let (expected, actual) = (pattern_trees.log_probability(password), mapped.log_probability(password));
+                assert!(actual == expected || (actual - expected).abs() < 1e-9, "{:?} {}", smoothing, password);
+            }
+        }
+        // Writing over the mapped file, as convert and update do, replaces it instead of truncating the mapping.
+        for write in [PatternTrees::write_encoding, PatternTrees::write_binary_encoding, PatternTrees::write_mapped_encoding] {
+            write(&mapped, path).unwrap();
+            let rewritten = PatternTreesFactory::new(4).from_encoding(path).unwrap();
+            assert_eq!(rewritten.log_probability("straße1"), mapped.log_probability("straße1"));
+        }
+        assert!(std::fs::read_dir(std::env::temp_dir()).unwrap()
+            .all(|entry| !entry.unwrap().file_name().to_string_lossy().starts_with("stochasticpwc_test_mapped_encoding.map.")));
+        let trees: Vec<u64> = pattern_trees.pattern_trees().iter().map(|tree| tree.total_follower_count).collect();
+        let mapped_trees: Vec<u64> = mapped.into_pattern_trees().iter().map(|tree| tree.total_follower_count).collect();
+        assert_eq!(mapped_trees, trees);
+        assert_eq!(PatternTreesFactory::new(2).from_encoding(path).unwrap().order(), 2);
+
+        let bytes = std::fs::read(path).unwrap();
+        std::fs::write(path, &bytes[..bytes.len() / 2]).unwrap();
+        assert!(MappedEncoding::open(path, 4).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
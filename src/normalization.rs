@@ -0,0 +1,39 @@
+use clap::ValueEnum;
+use unicode_normalization::UnicodeNormalization;
+
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Normalization {
+    /// Passwords are taken as they are
+    #[default]
+    None,
+    /// Canonical composition, "a" followed by a combining umlaut becomes "ä"
+    Nfc,
+    /// Compatibility composition, also folds full width and ligature letters like "ﬁ" into "fi"
+    Nfkc,
+}
+
+impl Normalization {
+    pub fn normalize(&self, password: &str) -> String {
+        match self {
+            Normalization::None => password.to_string(),
+            Normalization::Nfc => password.nfc().collect(),
+            Normalization::Nfkc => password.nfkc().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        let decomposed = "Stra\u{df}e a\u{308}";
+        assert_eq!(Normalization::None.normalize(decomposed), decomposed);
+        assert_eq!(Normalization::Nfc.normalize(decomposed), "Straße ä");
+        assert_eq!(Normalization::Nfc.normalize("\u{fb01}\u{ff11}"), "\u{fb01}\u{ff11}");
+        assert_eq!(Normalization::Nfkc.normalize("\u{fb01}\u{ff11}"), "fi1");
+        assert_eq!(Normalization::Nfkc.normalize("пароль密码"), "пароль密码");
+    }
+}
@@ -0,0 +1,192 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::budget::Budget;
+use crate::crack::CrackedHash;
+use crate::follower_tables::{FollowerTables, Followers};
+use crate::pattern_tree::END_SYMBOL;
+use crate::pattern_trees::PatternTrees;
+use crate::target::Target;
+
+
+/// Visits the words whose follower levels add up to one level, depth first with the pattern in one buffer.
+struct LevelSearch<'a, 'b> {
+    follower_tables: &'b mut FollowerTables<'a>,
+    has_end_symbol: bool,
+    max_len: usize,
+    pattern: String,
+    /// Whether a follower was left out for being above the level, so a higher level has words.
+    has_higher_levels: bool,
+}
+
+impl<'a, 'b> LevelSearch<'a, 'b> {
+    fn new(follower_tables: &'b mut FollowerTables<'a>, has_end_symbol: bool, max_len: usize) -> Self {
+        Self {
+            follower_tables,
+            has_end_symbol,
+            max_len,
+            pattern: String::new(),
+            has_higher_levels: false,
+        }
+    }
+
+    /// Visits the words that extend the pattern by followers whose levels add up to `remaining`.
+    /// Returns false once `try_password` does, which stops the search.
+    fn visit(&mut self, context: usize, letter_count: usize, remaining: u32,
+        try_password: &mut impl FnMut(&str) -> bool) -> bool {
+        let followers = self.follower_tables.followers(context, letter_count);
+        for index in 0..followers.count {
+            match self.visit_follower(&followers, letter_count, index, remaining, try_password) {
+                Some(true) => {},
+                Some(false) => return false,
+                None => break,
+            }
+        }
+        true
+    }
+
+    /// Like `visit` for the words that continue with one follower. Returns None if the follower is above
+    /// the remaining level or can't follow, the followers after it can't either.
+    fn visit_follower(&mut self, followers: &Followers, letter_count: usize, index: usize, remaining: u32,
+        try_password: &mut impl FnMut(&str) -> bool) -> Option<bool> {
+        let level = self.follower_tables.level(followers, index)?;
+        let (letter, _) = self.follower_tables.follower(followers, index);
+        if letter != END_SYMBOL && letter_count >= self.max_len {
+            return Some(true);
+        }
+        if level > remaining {
+            self.has_higher_levels = true;
+            return None;
+        }
+        if letter == END_SYMBOL {
+            return Some(level < remaining || try_password(&self.pattern));
+        }
+        let remaining = remaining - level;
+        let pattern_len = self.pattern.len();
+        self.pattern.push(letter);
+        let keep_going = (self.has_end_symbol || remaining > 0 || try_password(&self.pattern)) && {
+            let next_context = self.follower_tables.next_context(followers, index);
+            self.visit(next_context, letter_count + 1, remaining, try_password)
+        };
+        self.pattern.truncate(pattern_len);
+        Some(keep_going)
+    }
+}
+
+/// Ordered Markov ENumerator: every follower gets its `PatternTrees::level`, and all words of level sum 0
+/// are tried, then all of level sum 1 and so on, so only the path to the current word is kept in memory
+/// next to the follower tables. Each level is split into one work unit per follower of the empty word,
+/// which the threads take in turn, each with its own follower tables. The search ends once no follower
+/// was left out for its level or the budget is exhausted.
+pub fn crack_omen(pattern_trees: PatternTrees, budget: &Budget, mut target: Target, threads: usize) -> Vec<CrackedHash> {
+    let max_len = budget.max_len;
+    let mut cracked_hashes = vec![];
+    if !pattern_trees.has_end_symbol() && budget.is_long_enough("") {
+        if let Some(hash) = budget.next_guess().and_then(|_| target.crack("")) {
+            cracked_hashes.push(CrackedHash::new(hash, "".to_string(), 1));
+        }
+    }
+    let mut thread_follower_tables: Vec<FollowerTables> = (0..threads.max(1))
+        .map(|_| FollowerTables::new(&pattern_trees, max_len))
+        .collect();
+    let root_followers = thread_follower_tables[0].followers(thread_follower_tables[0].root(), 0);
+    let mut level = 0;
+    while !target.is_empty() && !budget.is_exhausted() {
+        let next_unit = AtomicUsize::new(0);
+        let found: Mutex<Vec<(String, u64)>> = Mutex::new(vec![]);
+        let stop = AtomicBool::new(false);
+        let has_higher_levels = AtomicBool::new(false);
+        thread::scope(|scope| {
+            for follower_tables in thread_follower_tables.iter_mut() {
+                let (pattern_trees, target) = (&pattern_trees, &target);
+                let (next_unit, found, stop, has_higher_levels) = (&next_unit, &found, &stop, &has_higher_levels);
+                scope.spawn(move || {
+                    let mut search = LevelSearch::new(follower_tables, pattern_trees.has_end_symbol(), max_len);
+                    let mut try_password = |password: &str| {
+                        if !budget.is_long_enough(password) {
+                            return !stop.load(Ordering::Relaxed);
+                        }
+                        let Some(guess) = budget.next_guess() else {
+                            stop.store(true, Ordering::Relaxed);
+                            return false;
+                        };
+                        if target.is_cracked_by(password) {
+                            let mut found = found.lock().unwrap();
+                            found.push((password.to_string(), guess));
+                            if found.len() >= target.len() {
+                                stop.store(true, Ordering::Relaxed);
+                            }
+                        }
+                        !stop.load(Ordering::Relaxed)
+                    };
+                    loop {
+                        let unit = next_unit.fetch_add(1, Ordering::Relaxed);
+                        if unit >= root_followers.count || stop.load(Ordering::Relaxed) { break; }
+                        search.visit_follower(&root_followers, 0, unit, level, &mut try_password);
+                    }
+                    if search.has_higher_levels {
+                        has_higher_levels.store(true, Ordering::Relaxed);
+                    }
+                });
+            }
+        });
+        let mut found = found.into_inner().unwrap();
+        found.sort_unstable_by_key(|(_, guess)| *guess);
+        for (password, guess) in found {
+            if let Some(hash) = target.crack(&password) {
+                cracked_hashes.push(CrackedHash::new(hash, password, guess));
+            }
+        }
+        if !has_higher_levels.load(Ordering::Relaxed) { break; }
+        level += 1;
+    }
+    cracked_hashes
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::pattern_trees_factory::PatternTreesFactory;
+    use crate::target::HashType;
+
+    #[test]
+    fn test_levels_partition_words() {
+        let pattern_trees = PatternTreesFactory::new(3).from_password_list("password_list_short.txt").unwrap();
+        let mut follower_tables = FollowerTables::new(&pattern_trees, 4);
+        let mut search = LevelSearch::new(&mut follower_tables, true, 4);
+        let mut words: Vec<(String, u32)> = vec![];
+        for level in 0..12 {
+            let root = search.follower_tables.root();
+            search.visit(root, 0, level, &mut |password| {
+                words.push((password.to_string(), level));
+                true
+            });
+        }
+        assert!(search.has_higher_levels);
+        assert!(words.len() > 100);
+        assert_eq!(words.iter().map(|(password, _)| password).collect::<HashSet<_>>().len(), words.len());
+        let level_of = |pattern: &str, letter: char| pattern_trees.level(pattern_trees.follower_probability(pattern, letter)).unwrap();
+        for (password, level) in words.iter() {
+            let letters_level: u32 = password.char_indices()
+                .map(|(index, letter)| level_of(&password[..index], letter))
+                .sum();
+            assert_eq!(letters_level + level_of(password, END_SYMBOL), *level);
+        }
+    }
+
+    #[test]
+    fn test_crack_omen() {
+        let pattern_trees = PatternTreesFactory::new(3).from_password_list("password_list_short.txt").unwrap();
+        for threads in [1, 4] {
+            let target = Target::new(HashType::Md5, "1a1dc91c907325c69271ddf0c944bc72").unwrap();
+            let cracked_hashes = crack_omen(pattern_trees.clone(), &Budget::new(0, 4, None, None), target, threads);
+            assert_eq!(cracked_hashes.len(), 1);
+            assert_eq!(cracked_hashes[0].password, "pass");
+        }
+        let target = Target::new(HashType::Plain, "not in the search space").unwrap();
+        assert!(crack_omen(pattern_trees, &Budget::new(0, 2, None, None), target, 2).is_empty());
+    }
+}
@@ -1,5 +1,10 @@
 use std::collections::BTreeMap;
 
+/// Marks the beginning of a password in the patterns, it is never a follower.
+pub const START_SYMBOL: char = '\u{2}';
+/// Follows the last letter of a password.
+pub const END_SYMBOL: char = '\u{3}';
+
 #[derive(Debug, Clone)]
 pub struct Follower {
     pub count: u32,
@@ -31,26 +36,37 @@ impl PatternTree {
         }
     }
 
-    pub fn insert(&mut self, pattern: &str, new_follower: Follower) {
-        self.total_follower_count += new_follower.count as u64;
+    /// Returns whether the pattern and follower pair is new. Counts stop at `u32::MAX`.
+    pub fn insert(&mut self, pattern: &str, new_follower: Follower) -> bool {
         if let Some(followers) = self.pattern_tree_implementation.get_mut(pattern) {
             for follower in followers.iter_mut() {
                 if follower.letter == new_follower.letter {
-                    follower.count += 1; 
-                    return;
+                    let count = follower.count.saturating_add(new_follower.count);
+                    self.total_follower_count += (count - follower.count) as u64;
+                    follower.count = count;
+                    return false;
                 }
             }
+            self.total_follower_count += new_follower.count as u64;
             followers.push(new_follower);
         } else {
+            self.total_follower_count += new_follower.count as u64;
             let mut followers = Vec::with_capacity(10);
             followers.push(new_follower);
             self.pattern_tree_implementation.insert(pattern.to_string(), followers);
         }
+        true
     }
 
-
-    pub fn patterns(&self) -> Vec<String> {
-       self.pattern_tree_implementation.clone().into_keys().collect::<Vec<String>>()
+    /// Adds the counts of the other tree multiplied by the weight. A pair the other tree has seen keeps
+    /// a count of at least 1, so a small weight doesn't lose it, and a large weight stops at `u32::MAX`.
+    pub fn merge(&mut self, other: &PatternTree, weight: f64) {
+        for (pattern, followers) in other.pattern_tree_implementation.iter() {
+            for follower in followers.iter() {
+                let count = (follower.count as f64 * weight).round().clamp(1.0, u32::MAX as f64) as u32;
+                self.insert(pattern, Follower::new(count, follower.letter));
+            }
+        }
     }
 
     pub fn probability_distribution(&self) -> BTreeMap<u64, f64> {
@@ -64,12 +80,47 @@ impl PatternTree {
         count_probabilities
     }
     
+    /// Good-Turing estimates (c + 1) * N(c + 1) / N(c) for the counts up to `max_count`,
+    /// where N(c) is the number of pattern and letter pairs seen c times.
+    pub fn good_turing_counts(&self, max_count: u64) -> BTreeMap<u64, f64> {
+        let probability_distribution = self.probability_distribution();
+        let mut good_turing_counts = BTreeMap::new();
+        for count in 1..=max_count {
+            if let (Some(probability), Some(next_probability)) =
+                (probability_distribution.get(&count), probability_distribution.get(&(count + 1))) {
+                good_turing_counts.insert(count, (count + 1) as f64 * next_probability / probability);
+            }
+        }
+        good_turing_counts
+    }
+
+    /// The absolute discount N(1) / (N(1) + 2 * N(2)) used by Kneser-Ney smoothing.
+    pub fn kneser_ney_discount(&self) -> f64 {
+        let probability_distribution = self.probability_distribution();
+        let once = probability_distribution.get(&1).copied().unwrap_or(0.0);
+        let twice = probability_distribution.get(&2).copied().unwrap_or(0.0);
+        if once + twice == 0.0 { 0.5 } else { once / (once + 2.0 * twice) }
+    }
+
+    /// The tree one order lower whose counts are the number of different letters preceding
+    /// each pattern and follower pair in this tree.
+    pub fn continuation_tree(&self) -> PatternTree {
+        let mut continuation_tree = PatternTree::new();
+        for (pattern, followers) in self.pattern_tree_implementation.iter() {
+            let suffix = pattern.chars().next().map_or("", |first_letter| &pattern[first_letter.len_utf8()..]);
+            for follower in followers.iter() {
+                continuation_tree.insert(suffix, Follower::new(1, follower.letter));
+            }
+        }
+        continuation_tree
+    }
+
     fn amount_counts(&self) -> BTreeMap<u64, u64> {
         let mut amount_counts: BTreeMap<u64, u64> = BTreeMap::new();
         for followers in self.pattern_tree_implementation.values() {
             for follower in followers.iter() {
                 match amount_counts.get_mut(&(follower.count as u64)) {
-                    Some(count) => *count += follower.count as u64,
+                    Some(count) => *count += 1,
                     None => {amount_counts.insert(follower.count as u64, 1);},
                 }
             }
@@ -1,7 +1,17 @@
-use std::fs::File;
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::io::{self, Write};
+use std::sync::Arc;
 
-use crate::pattern_tree::PatternTree;
+use rand::Rng;
+
+use crate::binary_encoding::{self, Metadata};
+use crate::mapped_encoding::{MappedEncoding, MappedFollowers};
+use crate::pattern_tree::{Follower, PatternTree, START_SYMBOL, END_SYMBOL};
+use crate::pattern_trees_factory::PatternTreesFactory;
+use crate::settings::Settings;
+use crate::smoothing::{Smoothing, SmoothingTables};
+use crate::text_encoding;
 
 
 #[derive(Debug, Clone)]
@@ -19,62 +29,593 @@ impl ProbableFollower {
     }
 }
 
+/// Where the counts are, read into pattern trees or queried in a mapped encoding.
 #[derive(Debug, Clone)]
-pub struct PatternTrees {
-    pattern_trees: Vec<PatternTree>,
+enum Counts {
+    Trees(Vec<PatternTree>),
+    Mapped(Arc<MappedEncoding>),
 }
 
-impl PatternTrees {
-    pub fn new(pattern_trees: Vec<PatternTree>) -> Self {
+/// The followers of a pattern, from either kind of counts.
+#[derive(Debug, Clone, Copy)]
+enum Followers<'a> {
+    Tree(&'a [Follower]),
+    Mapped(MappedFollowers<'a>),
+}
+
+impl Followers<'_> {
+    fn len(&self) -> usize {
+        match self {
+            Followers::Tree(followers) => followers.len(),
+            Followers::Mapped(followers) => followers.len(),
+        }
+    }
+}
+
+/// Index of each letter in the alphabet. It's looked up for every follower count, so the letters
+/// below U+0800, which covers most alphabets, are found in a table instead of being hashed.
+#[derive(Debug, Clone)]
+struct LetterIndices {
+    table: Vec<u32>,
+    others: HashMap<char, usize>,
+}
+
+impl LetterIndices {
+    const TABLE_LEN: usize = 0x800;
+
+    fn new(alphabet: &[char]) -> Self {
+        let mut table = vec![u32::MAX; Self::TABLE_LEN];
+        let mut others = HashMap::new();
+        for (index, letter) in alphabet.iter().enumerate() {
+            match table.get_mut(*letter as usize) {
+                Some(entry) => *entry = index as u32,
+                None => {others.insert(*letter, index);},
+            }
+        }
         Self {
-            pattern_trees,
-        } 
+            table,
+            others,
+        }
     }
 
-    pub fn alphabet(&self) -> Vec<char> {
-        self.pattern_trees[0].pattern_tree_implementation.get("")
+    fn get(&self, letter: char) -> Option<usize> {
+        match self.table.get(letter as usize) {
+            Some(&index) => (index != u32::MAX).then_some(index as usize),
+            None => self.others.get(&letter).copied(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PatternTrees {
+    counts: Counts,
+    settings: Settings,
+    alphabet: Vec<char>,
+    letter_indices: LetterIndices,
+    has_end_symbol: bool,
+    smoothing_tables: SmoothingTables,
+}
+
+impl PatternTrees {
+    /// The alphabet is ordered by how often the letters occur.
+    pub fn new(mut pattern_trees: Vec<PatternTree>, settings: Settings) -> Self {
+        pattern_trees[0].pattern_tree_implementation.get_mut("")
+            .unwrap()
+            .sort_unstable_by_key(|follower| (std::cmp::Reverse(follower.count), follower.letter));
+        let alphabet: Vec<char> = pattern_trees[0].pattern_tree_implementation.get("")
             .unwrap()
             .iter()
             .map(|follower| follower.letter)
-            .collect()
+            .collect();
+        let letter_indices = LetterIndices::new(&alphabet);
+        let has_end_symbol = alphabet.contains(&END_SYMBOL);
+        let smoothing_tables = SmoothingTables::new(settings.smoothing, &pattern_trees);
+        Self {
+            counts: Counts::Trees(pattern_trees),
+            settings,
+            alphabet,
+            letter_indices,
+            has_end_symbol,
+            smoothing_tables,
+        } 
     }
 
-    pub fn patterns(&self, pattern_tree_index: usize) -> Vec<String> {
-       self.pattern_trees[pattern_tree_index].patterns()
+    /// Queries the counts where they lie in the mapped encoding, which stores the smoothing tables as well.
+    pub fn from_mapped_encoding(mapped_encoding: MappedEncoding) -> Self {
+        let alphabet = mapped_encoding.alphabet.clone();
+        let letter_indices = LetterIndices::new(&alphabet);
+        let smoothing_tables = SmoothingTables {
+            good_turing_counts: mapped_encoding.good_turing_counts.clone(),
+            discounts: mapped_encoding.discounts.clone(),
+            continuation_trees: vec![],
+        };
+        Self {
+            has_end_symbol: alphabet.contains(&END_SYMBOL),
+            settings: mapped_encoding.settings.clone(),
+            alphabet,
+            letter_indices,
+            smoothing_tables,
+            counts: Counts::Mapped(Arc::new(mapped_encoding)),
+        }
     }
 
-    pub fn probable_followers(&self, pattern: &str) ->  Vec<ProbableFollower> {
-        let less_pattern_length_from_max: usize = 3;
-        let mut probable_followers: Vec<ProbableFollower> = self.alphabet()
-            .iter()
-            .map(|letter| ProbableFollower::new(*letter, 0.0))
+    /// Number of pattern trees.
+    pub fn order(&self) -> usize {
+        match &self.counts {
+            Counts::Trees(pattern_trees) => pattern_trees.len(),
+            Counts::Mapped(mapped_encoding) => mapped_encoding.order,
+        }
+    }
+
+    /// The pattern trees, read from a mapped encoding if they are queried there.
+    pub fn pattern_trees(&self) -> Cow<'_, [PatternTree]> {
+        match &self.counts {
+            Counts::Trees(pattern_trees) => Cow::Borrowed(pattern_trees),
+            Counts::Mapped(mapped_encoding) => Cow::Owned(mapped_encoding.pattern_trees()),
+        }
+    }
+
+    /// Models trained before passwords were framed by start and end symbols don't know where passwords end,
+    /// so every word they produce is a password.
+    pub fn has_end_symbol(&self) -> bool {
+        self.has_end_symbol
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// A mapped encoding keeps its smoothing tables, they cover every smoothing.
+    pub fn set_settings(&mut self, settings: Settings) {
+        if let Counts::Trees(pattern_trees) = &self.counts {
+            self.smoothing_tables = SmoothingTables::new(settings.smoothing, pattern_trees);
+        }
+        self.settings = settings;
+    }
+
+    pub fn into_pattern_trees(self) -> Vec<PatternTree> {
+        match self.counts {
+            Counts::Trees(pattern_trees) => pattern_trees,
+            Counts::Mapped(mapped_encoding) => mapped_encoding.pattern_trees(),
+        }
+    }
+
+    fn take_pattern_trees(&mut self) -> Vec<PatternTree> {
+        match std::mem::replace(&mut self.counts, Counts::Trees(vec![])) {
+            Counts::Trees(pattern_trees) => pattern_trees,
+            Counts::Mapped(mapped_encoding) => mapped_encoding.pattern_trees(),
+        }
+    }
+
+    /// Adds the counts of the other model multiplied by the weight, so a weight above 1 makes a small corpus
+    /// count for more. Letters only one model knows join the alphabet. If the orders differ the higher pattern
+    /// trees are dropped, since only one model has counts for them. The settings of this model are kept.
+    pub fn merge(&mut self, other: &PatternTrees, weight: f64) -> Result<(), io::Error> {
+        if self.has_end_symbol != other.has_end_symbol {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                "Can't merge a model with end symbols and one without"));
+        }
+        let order = self.order().min(other.order());
+        let mut pattern_trees = self.take_pattern_trees();
+        pattern_trees.truncate(order);
+        for (pattern_tree, other_pattern_tree) in pattern_trees.iter_mut().zip(other.pattern_trees().iter()) {
+            pattern_tree.merge(other_pattern_tree, weight);
+        }
+        let mut settings = self.settings.clone();
+        if let Some(interpolation_weights) = settings.interpolation_weights.as_mut() {
+            interpolation_weights.truncate(order);
+        }
+        *self = Self::new(pattern_trees, settings);
+        Ok(())
+    }
+
+    /// Probability of every letter of the alphabet following the pattern, most probable first.
+    pub fn follower_distribution(&self, pattern: &str) -> Vec<ProbableFollower> {
+        self.most_probable_followers(pattern, self.alphabet.len())
+    }
+
+    /// Probability of every letter of the alphabet following the pattern, in alphabet order.
+    fn letter_probabilities(&self, pattern: &str) -> Vec<f64> {
+        let pattern = self.context(pattern);
+        let (min_pattern_tree, max) = self.window(&pattern);
+        let mut probabilities = match self.settings.smoothing {
+            Smoothing::KneserNey => self.kneser_ney_probabilities(&pattern, min_pattern_tree, max),
+            _ => self.interpolated_probabilities(&pattern, min_pattern_tree, max),
+        };
+        self.anchor_end_probability(&pattern, max, &mut probabilities);
+        probabilities
+    }
+
+    /// The lower pattern trees count the end symbol after patterns anywhere in a password, so mixed in they
+    /// would let any word end after a few letters. While the whole pattern fits the longest context, which
+    /// starts with the start symbol, the end symbol only gets the probability of that context and the other
+    /// letters are scaled to the rest. A pattern the training never started with can't end yet.
+    fn anchor_end_probability(&self, pattern: &str, max: usize, probabilities: &mut [f64]) {
+        let Some(end_index) = self.letter_indices.get(END_SYMBOL) else { return };
+        if pattern.chars().count() != max - 1 {
+            return;
+        }
+        let end_probability = self.context_followers(max - 1, pattern)
+            .map_or(0.0, |tree_followers| self.context_probabilities(max - 1, tree_followers)[end_index]);
+        let letters_probability = 1.0 - probabilities[end_index];
+        if letters_probability > 0.0 {
+            let scale = (1.0 - end_probability) / letters_probability;
+            probabilities.iter_mut().for_each(|probability| *probability *= scale);
+        }
+        probabilities[end_index] = end_probability;
+    }
+
+    /// The `count` most probable followers, most probable first. Equally probable letters keep the order
+    /// of the alphabet, so the most probable followers are always the start of `follower_distribution`.
+    /// The end symbol is appended if it isn't among them, so a word can always end.
+    pub fn most_probable_followers(&self, pattern: &str, count: usize) -> Vec<ProbableFollower> {
+        self.top_followers(pattern, count, None)
+    }
+
+    /// Like `most_probable_followers`, cut to the nucleus if the pruning has one.
+    pub fn pruned_followers(&self, pattern: &str, count: usize) -> Vec<ProbableFollower> {
+        self.top_followers(pattern, count, self.settings.pruning.nucleus())
+    }
+
+    /// The `count` most probable followers, and only as many of them as it takes to reach the nucleus mass.
+    fn top_followers(&self, pattern: &str, count: usize, nucleus: Option<f64>) -> Vec<ProbableFollower> {
+        let probabilities = self.letter_probabilities(pattern);
+        let count = count.min(probabilities.len());
+        let descending = |a: &usize, b: &usize| probabilities[*b].total_cmp(&probabilities[*a]).then(a.cmp(b));
+        let mut letter_indices: Vec<usize> = (0..probabilities.len()).collect();
+        if count < letter_indices.len() {
+            letter_indices.select_nth_unstable_by(count, descending);
+            letter_indices.truncate(count);
+        }
+        letter_indices.sort_unstable_by(descending);
+        if let Some(nucleus) = nucleus {
+            let mut mass = 0.0;
+            let nucleus_len = letter_indices.iter()
+                .take_while(|index| {
+                    let is_below = mass < nucleus;
+                    mass += probabilities[**index];
+                    is_below
+                })
+                .count();
+            letter_indices.truncate(nucleus_len.max(1));
+        }
+        let mut probable_followers: Vec<ProbableFollower> = letter_indices.iter()
+            .map(|index| ProbableFollower::new(self.alphabet[*index], probabilities[*index]))
             .collect();
+        if let Some(end_index) = self.letter_indices.get(END_SYMBOL) {
+            if !probable_followers.iter().any(|follower| follower.letter == END_SYMBOL) {
+                probable_followers.push(ProbableFollower::new(END_SYMBOL, probabilities[end_index]));
+            }
+        }
+        probable_followers
+    }
 
-        let max = self.pattern_trees.len().min(pattern.len() + 1);
-        let min_pattern_tree = if max < less_pattern_length_from_max { 0 } else { max - less_pattern_length_from_max };
+    fn context(&self, pattern: &str) -> String {
+        if self.has_end_symbol { format!("{}{}", START_SYMBOL, pattern) } else { pattern.to_string() }
+    }
 
-        for index in min_pattern_tree..max {
-            if let Some(tree_followers) = self.pattern_trees[index].pattern_tree_implementation.get(&pattern[pattern.len() - index..]) {
-                for tree_follower in tree_followers.iter() {
-                    let probable_follower = probable_followers.iter_mut()
-                        .find(|probable_follower| probable_follower.letter == tree_follower.letter)
-                        .unwrap();
-                    probable_follower.probability += tree_follower.count as f64 
-                        / self.pattern_trees[index].total_follower_count as f64 
-                        / (max - min_pattern_tree) as f64;
+    /// The pattern trees used for a context, the longest fitting one and the ones below it within the backoff window.
+    fn window(&self, pattern: &str) -> (usize, usize) {
+        let max = self.order().min(pattern.chars().count() + 1);
+        (max.saturating_sub(self.settings.backoff_window), max)
+    }
+
+    fn context_followers(&self, pattern_tree_index: usize, pattern: &str) -> Option<Followers<'_>> {
+        let context = suffix(pattern, pattern_tree_index);
+        match &self.counts {
+            Counts::Trees(pattern_trees) => pattern_trees[pattern_tree_index].pattern_tree_implementation.get(context)
+                .map(|followers| Followers::Tree(followers)),
+            Counts::Mapped(mapped_encoding) => mapped_encoding.followers(pattern_tree_index, context).map(Followers::Mapped),
+        }
+    }
+
+    fn continuation_followers(&self, pattern_tree_index: usize, pattern: &str) -> Option<Followers<'_>> {
+        let context = suffix(pattern, pattern_tree_index);
+        match &self.counts {
+            Counts::Trees(_) => self.smoothing_tables.continuation_trees[pattern_tree_index].pattern_tree_implementation.get(context)
+                .map(|followers| Followers::Tree(followers)),
+            Counts::Mapped(mapped_encoding) => mapped_encoding.continuation_followers(pattern_tree_index, context).map(Followers::Mapped),
+        }
+    }
+
+    /// Letter indices and counts of the followers.
+    fn follower_counts<'a>(&'a self, followers: Followers<'a>) -> impl Iterator<Item = (usize, u32)> + 'a {
+        let (tree_followers, mapped_followers) = match followers {
+            Followers::Tree(followers) => (Some(followers.iter()
+                .map(|follower| (self.letter_indices.get(follower.letter).unwrap(), follower.count))), None),
+            Followers::Mapped(followers) => (None, Some(followers.iter())),
+        };
+        tree_followers.into_iter().flatten().chain(mapped_followers.into_iter().flatten())
+    }
+
+    /// The pattern trees of the window whose context occurs. If none does the letter frequencies are used.
+    fn occurring_contexts(&self, pattern: &str, min_pattern_tree: usize, max: usize) -> Vec<(usize, Followers<'_>)> {
+        let mut contexts: Vec<(usize, Followers<'_>)> = (min_pattern_tree..max)
+            .filter_map(|index| self.context_followers(index, pattern).map(|followers| (index, followers)))
+            .collect();
+        if contexts.is_empty() {
+            contexts.push((0, self.context_followers(0, pattern).unwrap()));
+        }
+        contexts
+    }
+
+    fn interpolation_weights(&self, contexts: &[(usize, Followers<'_>)]) -> Vec<f64> {
+        let weights: Vec<f64> = match &self.settings.interpolation_weights {
+            Some(interpolation_weights) => contexts.iter()
+                .map(|(index, _)| interpolation_weights.get(*index).copied().unwrap_or(0.0))
+                .collect(),
+            None => vec![1.0; contexts.len()],
+        };
+        let total: f64 = weights.iter().sum();
+        if total > 0.0 {
+            weights.iter().map(|weight| weight / total).collect()
+        } else {
+            vec![1.0 / contexts.len() as f64; contexts.len()]
+        }
+    }
+
+    /// Each pattern tree whose context occurs contributes its conditional probability,
+    /// weighted by the learned interpolation weights or equally without them.
+    fn interpolated_probabilities(&self, pattern: &str, min_pattern_tree: usize, max: usize) -> Vec<f64> {
+        let contexts = self.occurring_contexts(pattern, min_pattern_tree, max);
+        let weights = self.interpolation_weights(&contexts);
+        let mut probabilities = vec![0.0; self.alphabet.len()];
+        for ((index, tree_followers), weight) in contexts.iter().zip(weights) {
+            for (probability, context_probability) in probabilities.iter_mut()
+                .zip(self.context_probabilities(*index, *tree_followers)) {
+                *probability += weight * context_probability;
+            }
+        }
+        probabilities
+    }
+
+    /// Deleted interpolation: finds the weights of the pattern trees that maximize the likelihood of
+    /// passwords the counts weren't trained on with EM. Every letter of a held out password is an event
+    /// whose posterior per pattern tree is its weighted probability divided by the mixture.
+    /// As only the occurring contexts of the window are mixed, a weight is scaled by its summed posterior
+    /// over its summed normalized weight in the events it takes part in. Events count as often as their password.
+    pub fn learn_interpolation_weights(&self, held_out: &[(String, u32)], iterations: usize) -> Vec<f64> {
+        let mut events: Vec<(f64, Vec<(usize, f64)>)> = vec![];
+        for (password, count) in held_out.iter() {
+            let mut letters: Vec<char> = password.chars().collect();
+            if self.has_end_symbol {
+                letters.push(END_SYMBOL);
+            }
+            let mut pattern = self.context("");
+            for letter in letters {
+                let Some(letter_index) = self.letter_indices.get(letter) else { break };
+                let (min_pattern_tree, max) = self.window(&pattern);
+                let event: Vec<(usize, f64)> = self.occurring_contexts(&pattern, min_pattern_tree, max)
+                    .iter()
+                    .map(|(index, tree_followers)| (*index, self.context_probabilities(*index, *tree_followers)[letter_index]))
+                    .collect();
+                if event.iter().any(|(_, probability)| *probability > 0.0) {
+                    events.push((*count as f64, event));
                 }
+                pattern.push(letter);
             }
         }
-        probable_followers.sort_unstable_by(
-            |a, b| b.probability.partial_cmp(&a.probability).unwrap()
-            );
-        probable_followers.into_iter()
-            .take(Self::followers_for_pattern_length(pattern.len()))
-            .collect()
+
+        let mut weights = vec![1.0 / self.order() as f64; self.order()];
+        for _ in 0..iterations {
+            let mut posteriors = vec![0.0; weights.len()];
+            let mut normalized_weights = vec![0.0; weights.len()];
+            for (count, event) in events.iter() {
+                let weight_sum: f64 = event.iter().map(|(index, _)| weights[*index]).sum();
+                let mixture: f64 = event.iter().map(|(index, probability)| weights[*index] * probability).sum();
+                for (index, probability) in event.iter() {
+                    posteriors[*index] += count * weights[*index] * probability / mixture;
+                    normalized_weights[*index] += count * weights[*index] / weight_sum;
+                }
+            }
+            for (index, weight) in weights.iter_mut().enumerate() {
+                if normalized_weights[index] > 0.0 {
+                    *weight *= posteriors[index] / normalized_weights[index];
+                }
+            }
+            let total: f64 = weights.iter().sum();
+            weights.iter_mut().for_each(|weight| *weight /= total);
+        }
+        weights
     }
 
-    fn followers_for_pattern_length(length: usize) -> usize {
-        60 / (length + 1) + 1
+    fn context_probabilities(&self, pattern_tree_index: usize, tree_followers: Followers<'_>) -> Vec<f64> {
+        let context_count: f64 = self.follower_counts(tree_followers).map(|(_, count)| count as f64).sum();
+        let alphabet_len = self.alphabet.len() as f64;
+        let mut probabilities = vec![0.0; self.alphabet.len()];
+        match self.settings.smoothing {
+            Smoothing::None | Smoothing::KneserNey => {
+                for (letter_index, count) in self.follower_counts(tree_followers) {
+                    probabilities[letter_index] = count as f64 / context_count;
+                }
+            }
+            Smoothing::Additive => {
+                let alpha = self.settings.additive_alpha;
+                let denominator = context_count + alpha * alphabet_len;
+                probabilities.fill(alpha / denominator);
+                for (letter_index, count) in self.follower_counts(tree_followers) {
+                    probabilities[letter_index] = (count as f64 + alpha) / denominator;
+                }
+            }
+            Smoothing::GoodTuring => {
+                let good_turing_counts = &self.smoothing_tables.good_turing_counts[pattern_tree_index];
+                for (letter_index, count) in self.follower_counts(tree_followers) {
+                    let good_turing_count = good_turing_counts.get(&(count as u64))
+                        .copied()
+                        .unwrap_or(count as f64);
+                    probabilities[letter_index] = good_turing_count / context_count;
+                }
+                let unseen_letters = self.alphabet.len() - tree_followers.len();
+                let seen_probability: f64 = probabilities.iter().sum();
+                if unseen_letters > 0 && seen_probability < 1.0 {
+                    let unseen_probability = (1.0 - seen_probability) / unseen_letters as f64;
+                    for probability in probabilities.iter_mut().filter(|probability| **probability == 0.0) {
+                        *probability = unseen_probability;
+                    }
+                }
+                let total: f64 = probabilities.iter().sum();
+                probabilities.iter_mut().for_each(|probability| *probability /= total);
+            }
+        }
+        probabilities
+    }
+
+    /// Starts from the uniform distribution and interpolates each order from the lowest up: the discounted
+    /// counts of the order plus the lower order distribution weighted by the discounted probability.
+    /// All but the highest order count the different letters a pattern follows instead of how often it occurs.
+    fn kneser_ney_probabilities(&self, pattern: &str, min_pattern_tree: usize, max: usize) -> Vec<f64> {
+        let mut probabilities = vec![1.0 / self.alphabet.len() as f64; self.alphabet.len()];
+        for index in min_pattern_tree..max {
+            let continuation_followers = if index + 1 < max {
+                self.continuation_followers(index, pattern)
+            } else {
+                None
+            };
+            let Some(tree_followers) = continuation_followers.or_else(|| self.context_followers(index, pattern)) else {
+                continue;
+            };
+            let context_count: f64 = self.follower_counts(tree_followers).map(|(_, count)| count as f64).sum();
+            let discount = self.smoothing_tables.discounts[index];
+            let lower_order_weight = discount * tree_followers.len() as f64 / context_count;
+            probabilities.iter_mut().for_each(|probability| *probability *= lower_order_weight);
+            for (letter_index, count) in self.follower_counts(tree_followers) {
+                probabilities[letter_index] += (count as f64 - discount).max(0.0) / context_count;
+            }
+        }
+        probabilities
+    }
+
+    /// What a follower of this probability adds to the base 2 log probability words are ranked by.
+    /// With a level width it's the level times the negated width, so words are ranked by the sum
+    /// of their levels and words of the same level sum tie.
+    pub fn log_step(&self, probability: f64) -> f64 {
+        match (self.settings.level_width, self.level(probability)) {
+            (Some(level_width), Some(level)) => -(level as f64) * level_width,
+            (Some(_), None) => f64::NEG_INFINITY,
+            (None, _) => probability.log2(),
+        }
+    }
+
+    /// OMEN style level round(-log2(p) / width) of a follower, one bit wide without a level width.
+    /// Letters that can't follow have no level.
+    pub fn level(&self, probability: f64) -> Option<u32> {
+        if probability <= 0.0 {
+            return None;
+        }
+        let level_width = self.settings.level_width.unwrap_or(1.0);
+        Some((-probability.log2() / level_width).round().max(0.0) as u32)
+    }
+
+    /// The followers the pruning keeps, the end symbol is kept even if it isn't among them.
+    pub fn probable_followers(&self, pattern: &str) ->  Vec<ProbableFollower> {
+        self.pruned_followers(pattern, self.settings.pruning.followers_for_pattern_length(pattern.chars().count()))
+    }
+
+    pub fn follower_probability(&self, pattern: &str, letter: char) -> f64 {
+        self.letter_indices.get(letter)
+            .map_or(0.0, |letter_index| self.letter_probabilities(pattern)[letter_index])
+    }
+
+    /// Counts the password `count` more times, as if it had been in the training list that often.
+    /// Only the counts of its patterns change, and the Kneser-Ney continuation counts with them. The
+    /// statistics of the whole model, the Good-Turing estimates, the discounts and the order of the
+    /// alphabet, are kept. A mapped encoding is read into pattern trees first, and a model that gains
+    /// a letter is built anew.
+    pub fn add_password(&mut self, password: &str, count: u32) {
+        let knows_letters = self.has_end_symbol && password.chars().all(|letter| self.letter_indices.get(letter).is_some());
+        match &mut self.counts {
+            Counts::Trees(pattern_trees) if knows_letters => {
+                let continuation_trees = &mut self.smoothing_tables.continuation_trees;
+                PatternTreesFactory::insert_password_with(pattern_trees, password.to_string(), count,
+                    |pattern_tree_index, pattern, letter| {
+                        if pattern_tree_index > 0 && !continuation_trees.is_empty() {
+                            continuation_trees[pattern_tree_index - 1]
+                                .insert(suffix(pattern, pattern_tree_index - 1), Follower::new(1, letter));
+                        }
+                    });
+            }
+            _ => {
+                let settings = self.settings.clone();
+                let mut pattern_trees = self.take_pattern_trees();
+                PatternTreesFactory::insert_password(&mut pattern_trees, password.to_string(), count);
+                *self = Self::new(pattern_trees, settings);
+            }
+        }
+    }
+
+    /// Whether adding the passwords changes the probabilities of the letters following the pattern.
+    /// A context of the window changes if a password has it followed by a letter. If no context
+    /// occurs the letter frequencies are used, which every password changes.
+    pub fn is_changed_by(&self, pattern: &str, passwords: &[String]) -> bool {
+        if !self.has_end_symbol {
+            return !passwords.is_empty();
+        }
+        let context = self.context(pattern);
+        let (min_pattern_tree, max) = self.window(&context);
+        let occurs = (min_pattern_tree..max).any(|index| self.context_followers(index, &context).is_some());
+        passwords.iter().any(|password| !occurs || (min_pattern_tree..max).any(|index| {
+            let context_suffix = suffix(&context, index);
+            match context_suffix.strip_prefix(START_SYMBOL) {
+                Some(start) => password.starts_with(start),
+                None => password.contains(context_suffix),
+            }
+        }))
+    }
+
+    /// Product of the probabilities of each letter following the letters before it
+    /// and of the end symbol following the whole password.
+    pub fn probability(&self, password: &str) -> f64 {
+        self.log_probability(password).exp2()
+    }
+
+    /// Base 2 logarithm of `probability`, so its negation is the strength of the password in bits.
+    pub fn log_probability(&self, password: &str) -> f64 {
+        let mut log_probability = 0.0;
+        for (index, letter) in password.char_indices() {
+            let probability = self.follower_probability(&password[..index], letter);
+            if probability == 0.0 {
+                return f64::NEG_INFINITY;
+            }
+            log_probability += probability.log2();
+        }
+        if self.has_end_symbol {
+            log_probability + self.follower_probability(password, END_SYMBOL).log2()
+        } else {
+            log_probability
+        }
+    }
+
+    /// Draws letters from `follower_distribution` until the end symbol is drawn or the password
+    /// is `max_len` letters long and returns it with its base 2 log probability under the model.
+    /// A temperature below 1 favours probable letters, one above 1 flattens the distribution.
+    pub fn sample(&self, rng: &mut impl Rng, max_len: usize, temperature: Option<f64>) -> (String, f64) {
+        let mut password = String::new();
+        let mut log_probability = 0.0;
+        for _ in 0..max_len {
+            let probable_followers = self.follower_distribution(&password);
+            let weights: Vec<f64> = probable_followers.iter()
+                .map(|probable_follower| match temperature {
+                    Some(temperature) => probable_follower.probability.powf(1.0 / temperature),
+                    None => probable_follower.probability,
+                })
+                .collect();
+            let mut threshold: f64 = rng.gen::<f64>() * weights.iter().sum::<f64>();
+            let mut chosen = &probable_followers[0];
+            for (probable_follower, weight) in probable_followers.iter().zip(weights.iter()) {
+                if *weight <= 0.0 { break; }
+                chosen = probable_follower;
+                threshold -= weight;
+                if threshold < 0.0 { break; }
+            }
+            log_probability += chosen.probability.log2();
+            if chosen.letter == END_SYMBOL { break; }
+            password.push(chosen.letter);
+        }
+        (password, log_probability)
+    }
+
+    pub fn normalize(&self, password: &str) -> String {
+        self.settings.normalization.normalize(password)
     }
 
     pub fn write_with_error_handling(&self, write_function: fn(&PatternTrees, &str) -> Result<(),
@@ -96,30 +637,295 @@ impl PatternTrees {
     }
 
     pub fn write_probability_distribution(&self, path: &str) -> Result<(), io::Error> {
-        let mut output = File::create(path)?;
-        writeln!(output, "PROBABILITY DISTRIBUTIONS")?;
-        for (index, pattern_tree) in self.pattern_trees.iter().enumerate() {
-            writeln!(output, "PATTERNTREE {}", index)?;
-            for (count, count_probability) in pattern_tree.probability_distribution().iter() {
-                write!(output, "{:<8} ", count)?;
-                writeln!(output, "{:.8} ", count_probability)?;
+        binary_encoding::replace_file(path, |output| {
+            writeln!(output, "PROBABILITY DISTRIBUTIONS")?;
+            for (index, pattern_tree) in self.pattern_trees().iter().enumerate() {
+                writeln!(output, "PATTERNTREE {}", index)?;
+                for (count, count_probability) in pattern_tree.probability_distribution().iter() {
+                    write!(output, "{:<8} ", count)?;
+                    writeln!(output, "{:.8} ", count_probability)?;
+                }
+                writeln!(output, "\n")?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Passwords the model was trained on, each ends once.
+    pub fn training_size(&self) -> u64 {
+        let (Some(followers), Some(end_index)) = (self.context_followers(0, ""), self.letter_indices.get(END_SYMBOL)) else {
+            return 0;
+        };
+        self.follower_counts(followers)
+            .find(|(letter_index, _)| *letter_index == end_index)
+            .map_or(0, |(_, count)| count as u64)
+    }
+
+    pub fn metadata(&self) -> Metadata {
+        Metadata::new(self.order(), self.training_size(), self.alphabet.clone(), self.settings.clone())
+    }
+
+    /// Writes the layout described at `Metadata`, read back by `from_encoding`.
+    pub fn write_binary_encoding(&self, path: &str) -> Result<(), io::Error> {
+        let mut output: Vec<u8> = vec![];
+        self.metadata().write(&mut output);
+        for pattern_tree in self.pattern_trees().iter() {
+            binary_encoding::write_u32(&mut output, pattern_tree.pattern_tree_implementation.len() as u32);
+            for (pattern, followers) in pattern_tree.pattern_tree_implementation.iter() {
+                binary_encoding::write_str(&mut output, pattern);
+                binary_encoding::write_u32(&mut output, followers.len() as u32);
+                for follower in followers.iter() {
+                    binary_encoding::write_u32(&mut output, follower.letter as u32);
+                    binary_encoding::write_u32(&mut output, follower.count);
+                }
             }
-            writeln!(output, "\n")?;
         }
-        Ok(())
+        binary_encoding::append_checksum(&mut output);
+        binary_encoding::replace_file(path, |file| file.write_all(&output))
+    }
+
+    /// Writes the layout described at `MappedEncoding`.
+    pub fn write_mapped_encoding(&self, path: &str) -> Result<(), io::Error> {
+        let pattern_trees = self.pattern_trees();
+        MappedEncoding::write(path, &pattern_trees, &self.alphabet, &self.settings, &SmoothingTables::all(&pattern_trees))
     }
 
+    /// Writes the second text format, see `text_encoding`.
     pub fn write_encoding(&self, path: &str) -> Result<(), io::Error> {
-        let mut output = File::create(path)?;
-        for pattern_tree in self.pattern_trees.iter() {
+        binary_encoding::replace_file(path, |output| self.write_text_encoding(output))
+    }
+
+    fn write_text_encoding(&self, output: &mut impl Write) -> Result<(), io::Error> {
+        writeln!(output, "{}", text_encoding::HEADER)?;
+        for setting_line in self.settings.encoding_lines() {
+            writeln!(output, "{}", setting_line)?;
+        }
+        for pattern_tree in self.pattern_trees().iter() {
             for (pattern, followers) in pattern_tree.pattern_tree_implementation.iter() {
                 for follower in followers {
-                    write!(output, "{}", pattern)?;
-                    writeln!(output, "{}{}", follower.letter, follower.count)?;
+                    writeln!(output, "{}", text_encoding::record(pattern, follower.letter, follower.count))?;
                 }
             }
-            writeln!(output, "---")?;
+            writeln!(output, "{}", text_encoding::TREE_END)?;
         }
         Ok(())
     }
 }
+
+/// The last `length` letters of the pattern, which has at least that many.
+fn suffix(pattern: &str, length: usize) -> &str {
+    if length == 0 {
+        return "";
+    }
+    pattern.char_indices().rev().nth(length - 1).map_or(pattern, |(index, _)| &pattern[index..])
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+    use crate::normalization::Normalization;
+    use crate::pruning::Pruning;
+
+    #[test]
+    fn test_probability() {
+        let pattern_trees = PatternTreesFactory::new(3).from_password_list("password_list_short.txt").unwrap();
+        let total: f64 = pattern_trees.follower_distribution("pas").iter().map(|follower| follower.probability).sum();
+        assert!((total - 1.0).abs() < 1e-9);
+
+        assert!(pattern_trees.has_end_symbol());
+        assert_eq!(pattern_trees.probability(""), pattern_trees.follower_probability("", END_SYMBOL));
+        let expected = pattern_trees.follower_probability("", 'p')
+            * pattern_trees.follower_probability("p", 'a')
+            * pattern_trees.follower_probability("pa", 's')
+            * pattern_trees.follower_probability("pas", 's')
+            * pattern_trees.follower_probability("pass", END_SYMBOL);
+        assert!((pattern_trees.probability("pass") - expected).abs() < 1e-12);
+        assert!((pattern_trees.log_probability("pass") - expected.log2()).abs() < 1e-9);
+        assert!(pattern_trees.probability("password") > pattern_trees.probability("pxsqword"));
+        assert_eq!(pattern_trees.log_probability("pass\u{1F600}"), f64::NEG_INFINITY);
+    }
+
+    #[test]
+    fn test_end_symbol_follows_start() {
+        for smoothing in [Smoothing::None, Smoothing::KneserNey] {
+            let mut factory = PatternTreesFactory::new(5);
+            factory.settings.smoothing = smoothing;
+            let pattern_trees = factory.from_password_list("probable-v2-wpa-top4800.txt").unwrap();
+            let one_letter: f64 = pattern_trees.alphabet.iter().map(|letter| pattern_trees.probability(&letter.to_string())).sum();
+            assert!(one_letter < 1e-4);
+            let total: f64 = pattern_trees.follower_distribution("s").iter().map(|follower| follower.probability).sum();
+            assert!((total - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_sample() {
+        let pattern_trees = PatternTreesFactory::new(3).from_password_list("password_list_short.txt").unwrap();
+        let sample = |seed: u64, temperature: Option<f64>| -> Vec<(String, f64)> {
+            let mut rng = ChaCha8Rng::seed_from_u64(seed);
+            (0..20).map(|_| pattern_trees.sample(&mut rng, 6, temperature)).collect()
+        };
+        assert_eq!(sample(3, None), sample(3, None));
+        assert_ne!(sample(3, None), sample(4, None));
+        for (password, log_probability) in sample(3, Some(1.5)) {
+            assert!(password.chars().count() <= 6);
+            if password.chars().count() < 6 {
+                assert!((pattern_trees.log_probability(&password) - log_probability).abs() < 1e-9);
+            }
+        }
+
+        let mut greedy = String::new();
+        while greedy.len() < 6 {
+            let letter = pattern_trees.follower_distribution(&greedy)[0].letter;
+            if letter == END_SYMBOL { break; }
+            greedy.push(letter);
+        }
+        assert!(sample(5, Some(0.01)).iter().all(|(password, _)| *password == greedy));
+    }
+
+    #[test]
+    fn test_smoothing() {
+        let mut pattern_trees = PatternTreesFactory::new(4).from_password_list("password_list_short.txt").unwrap();
+        for smoothing in [Smoothing::None, Smoothing::Additive, Smoothing::GoodTuring, Smoothing::KneserNey] {
+            pattern_trees.set_settings(Settings { smoothing, additive_alpha: 0.5, ..Settings::default() });
+            for pattern in ["", "p", "pas", "passw", "qqq"] {
+                let total: f64 = pattern_trees.follower_distribution(pattern).iter().map(|follower| follower.probability).sum();
+                assert!((total - 1.0).abs() < 1e-9, "{:?} sums to {} after {}", smoothing, total, pattern);
+            }
+            let unseen_probability = pattern_trees.follower_probability("passw", '1');
+            assert_eq!(unseen_probability > 0.0, smoothing != Smoothing::None, "{:?}", smoothing);
+        }
+    }
+
+    #[test]
+    fn test_settings_in_encoding() {
+        let factory = PatternTreesFactory::new(3);
+        let mut pattern_trees = factory.from_password_list("password_list_short.txt").unwrap();
+        pattern_trees.set_settings(Settings {
+            smoothing: Smoothing::GoodTuring,
+            additive_alpha: 0.25,
+            backoff_window: 2,
+            interpolation_weights: Some(vec![0.125, 0.5, 0.375]),
+            normalization: Normalization::Nfkc,
+            level_width: Some(0.5),
+            pruning: Pruning::PerPosition(vec![30, 10, 4]),
+        });
+        let path = std::env::temp_dir().join("stochasticpwc_test_settings_encoding.txt");
+        pattern_trees.write_encoding(path.to_str().unwrap()).unwrap();
+        let read_pattern_trees = factory.from_encoding(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(read_pattern_trees.settings(), pattern_trees.settings());
+        assert_eq!(read_pattern_trees.log_probability("password"), pattern_trees.log_probability("password"));
+    }
+
+    #[test]
+    fn test_learn_interpolation_weights() {
+        let mut pattern_trees = PatternTreesFactory::new(4).from_password_list("test_passwords_list.txt").unwrap();
+        let held_out: Vec<(String, u32)> = std::fs::read_to_string("probable-v2-wpa-top4800.txt").unwrap()
+            .lines()
+            .take(500)
+            .map(|line| (line.to_string(), 1))
+            .collect();
+        let weights = pattern_trees.learn_interpolation_weights(&held_out, 20);
+        assert_eq!(weights.len(), 4);
+        assert!((weights.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+
+        let log_likelihood = |pattern_trees: &PatternTrees| -> f64 {
+            held_out.iter().map(|(password, _)| pattern_trees.log_probability(password)).filter(|log_probability| log_probability.is_finite()).sum()
+        };
+        let equal_log_likelihood = log_likelihood(&pattern_trees);
+        pattern_trees.set_settings(Settings { interpolation_weights: Some(weights), ..Settings::default() });
+        assert!(log_likelihood(&pattern_trees) > equal_log_likelihood);
+    }
+
+    #[test]
+    fn test_unicode() {
+        let mut factory = PatternTreesFactory::new(4);
+        factory.settings.normalization = Normalization::Nfc;
+        let pattern_trees = factory.from_password_list("password_list_unicode.txt").unwrap();
+        for password in ["straße", "пароль1", "我爱你1314"] {
+            assert!(pattern_trees.log_probability(password).is_finite(), "{}", password);
+        }
+        assert!(pattern_trees.follower_probability("прив", 'е') > 0.5);
+        let decomposed = pattern_trees.normalize("mu\u{308}nchen");
+        assert_eq!(decomposed, "münchen");
+        assert!(pattern_trees.log_probability(&decomposed).is_finite());
+
+        let path = std::env::temp_dir().join("stochasticpwc_test_unicode_encoding.txt");
+        pattern_trees.write_encoding(path.to_str().unwrap()).unwrap();
+        let read_pattern_trees = factory.from_encoding(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(read_pattern_trees.settings().normalization, Normalization::Nfc);
+        for password in ["größe", "любовь", "密码123"] {
+            assert_eq!(read_pattern_trees.log_probability(password), pattern_trees.log_probability(password));
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let path = std::env::temp_dir().join("stochasticpwc_test_merge_list.txt");
+        let short = std::fs::read_to_string("password_list_short.txt").unwrap();
+        let unicode = std::fs::read_to_string("password_list_unicode.txt").unwrap();
+        std::fs::write(&path, format!("{}{}{}", short, unicode, unicode)).unwrap();
+        let combined = PatternTreesFactory::new(3).from_password_list(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let mut merged = PatternTreesFactory::new(3).from_password_list("password_list_short.txt").unwrap();
+        let other = PatternTreesFactory::new(4).from_password_list("password_list_unicode.txt").unwrap();
+        merged.merge(&other, 2.0).unwrap();
+        assert_eq!(merged.order(), 3);
+        assert_eq!(merged.alphabet, combined.alphabet);
+        for password in ["password", "пароль", "straße1", "pass密"] {
+            assert_eq!(merged.log_probability(password), combined.log_probability(password));
+        }
+        let total_follower_count = |pattern_trees: &PatternTrees| -> Vec<u64> {
+            pattern_trees.pattern_trees().iter().map(|pattern_tree| pattern_tree.total_follower_count).collect()
+        };
+        assert_eq!(total_follower_count(&merged), total_follower_count(&combined));
+
+        let short = PatternTreesFactory::new(3).from_password_list("password_list_short.txt").unwrap();
+        let mut saturated = short.clone();
+        saturated.merge(&short, 2e9).unwrap();
+        saturated.merge(&short, 2e9).unwrap();
+        saturated.add_password("123456", u32::MAX);
+        for pattern_tree in saturated.pattern_trees().iter() {
+            let counts: Vec<u32> = pattern_tree.pattern_tree_implementation.values().flatten().map(|follower| follower.count).collect();
+            assert!(counts.contains(&u32::MAX));
+            assert_eq!(pattern_tree.total_follower_count, counts.iter().map(|count| *count as u64).sum::<u64>());
+        }
+    }
+
+    #[test]
+    fn test_add_password() {
+        for smoothing in [Smoothing::None, Smoothing::KneserNey] {
+            let mut factory = PatternTreesFactory::new(3);
+            factory.settings.smoothing = smoothing;
+            factory.settings.backoff_window = 2;
+            let original = factory.from_password_list("password_list_short.txt").unwrap();
+            let mut updated = original.clone();
+            updated.add_password("dragon1", 5);
+            updated.add_password("dragon12", 5);
+            let mut rebuilt = PatternTrees::new(updated.pattern_trees().to_vec(), updated.settings.clone());
+            rebuilt.smoothing_tables.discounts = updated.smoothing_tables.discounts.clone();
+            for password in ["dragon12", "password", "drag", "1"] {
+                let probability = updated.probability(password);
+                assert!((probability - rebuilt.probability(password)).abs() <= 1e-9 * probability);
+            }
+            let mut unchanged = 0;
+            for pattern in ["", "d", "drago", "pass", "passw", "sun", "qwert", "12"] {
+                if !original.is_changed_by(pattern, &["dragon1".to_string()]) {
+                    unchanged += 1;
+                    let mut updated = original.clone();
+                    updated.add_password("dragon1", 5);
+                    assert_eq!(original.letter_probabilities(pattern), updated.letter_probabilities(pattern));
+                }
+            }
+            assert!(unchanged > 0);
+            assert!(original.is_changed_by("drago", &["sun".to_string(), "dragon1".to_string()]));
+            assert!(!original.is_changed_by("drago", &[]));
+        }
+    }
+}
@@ -1,18 +1,66 @@
 use std::fs::File;
 use std::io::{self, BufReader, BufRead};
 
-use crate::pattern_tree::{PatternTree, Follower};
+use clap::ValueEnum;
+
+use crate::binary_encoding::{self, BinaryReader, Metadata};
+use crate::mapped_encoding::{self, MappedEncoding};
+use crate::normalization::Normalization;
+use crate::pattern_tree::{PatternTree, Follower, START_SYMBOL, END_SYMBOL};
 use crate::pattern_trees::PatternTrees;
+use crate::settings::Settings;
+use crate::smoothing::Smoothing;
+use crate::text_encoding;
+
+
+/// Iterations of EM when learning the interpolation weights.
+const INTERPOLATION_WEIGHT_ITERATIONS: usize = 20;
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ListFormat {
+    /// One password per line, each line counts once
+    #[default]
+    Plain,
+    /// `count password` lines as written by `uniq -c`, leading spaces are ignored
+    CountPrefixed,
+    /// `password:count` lines, the password may contain colons
+    ColonCount,
+}
 
+impl ListFormat {
+    /// Splits a line into the password and how often it occurs.
+    pub fn parse_line<'a>(&self, line: &'a str) -> Result<(&'a str, u32), String> {
+        let (password, count) = match self {
+            ListFormat::Plain => return Ok((line, 1)),
+            ListFormat::CountPrefixed => line.trim_start().split_once(' ')
+                .map(|(count, password)| (password, count))
+                .ok_or("Expected a count and a password separated by a space")?,
+            ListFormat::ColonCount => line.rsplit_once(':')
+                .ok_or("Expected a password and a count separated by a colon")?,
+        };
+        let count = count.parse::<u32>().map_err(|err| format!("Invalid count {}: {}", count, err))?;
+        Ok((password, count))
+    }
+}
 
 pub struct PatternTreesFactory {
     count_pattern_trees: usize,
+    /// Used for models from password lists, encodings bring their own.
+    pub settings: Settings,
+    /// Share of the password list held out to learn the interpolation weights on.
+    pub held_out_fraction: Option<f64>,
+    pub list_format: ListFormat,
 }
 
+// The `from_` readers take `self` for the order and settings of the factory, they keep the names of the first version.
+#[allow(clippy::wrong_self_convention)]
 impl PatternTreesFactory {
     pub fn new(count_pattern_trees: usize) -> Self {
         Self {
             count_pattern_trees,
+            settings: Settings::default(),
+            held_out_fraction: None,
+            list_format: ListFormat::Plain,
         }
     }
 
@@ -36,13 +84,53 @@ impl PatternTreesFactory {
     }
 
     pub fn from_password_list(&self, path: &str) -> Result<PatternTrees, io::Error> {
+        if self.held_out_fraction.is_some() && self.settings.smoothing == Smoothing::KneserNey {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                "Kneser-Ney smoothing mixes the pattern trees by its discounts, it can't use learned interpolation weights"));
+        }
         let mut pattern_trees: Vec<PatternTree> = vec![];
         for _ in 0..self.count_pattern_trees {
             pattern_trees.push(PatternTree::new());
         }
+        let held_out = self.insert_password_list(&mut pattern_trees, path, self.settings.normalization, self.held_out_fraction)?;
+        if pattern_trees[0].total_follower_count == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("No passwords of {} are left to train on, {} are held out", path, held_out.len())));
+        }
+        if held_out.is_empty() {
+            return Ok(PatternTrees::new(pattern_trees, self.settings.clone()));
+        }
+
+        let pattern_trees = PatternTrees::new(pattern_trees, self.settings.clone());
+        let mut settings = self.settings.clone();
+        settings.interpolation_weights = Some(pattern_trees.learn_interpolation_weights(&held_out, INTERPOLATION_WEIGHT_ITERATIONS));
+        let mut pattern_trees = pattern_trees.into_pattern_trees();
+        for (password, count) in held_out {
+            Self::insert_password(&mut pattern_trees, password, count);
+        }
+        Ok(PatternTrees::new(pattern_trees, settings))
+    }
+
+    /// Adds the passwords of a list to a model, as if they had been part of the list it was trained on.
+    /// They are normalized like the model's training passwords, its learned interpolation weights are kept.
+    pub fn add_password_list(&self, pattern_trees: PatternTrees, path: &str) -> Result<PatternTrees, io::Error> {
+        if !pattern_trees.has_end_symbol() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                "The model has no end symbol, retrain it from its password list to add passwords"));
+        }
+        let settings = pattern_trees.settings().clone();
+        let mut pattern_trees = pattern_trees.into_pattern_trees();
+        self.insert_password_list(&mut pattern_trees, path, settings.normalization, None)?;
+        Ok(PatternTrees::new(pattern_trees, settings))
+    }
+
+    /// Inserts the passwords of the list and returns the held out fraction of its lines instead of inserting them.
+    fn insert_password_list(&self, pattern_trees: &mut [PatternTree], path: &str, normalization: Normalization,
+        held_out_fraction: Option<f64>) -> Result<Vec<(String, u32)>, io::Error> {
+        let mut held_out: Vec<(String, u32)> = vec![];
         let file = File::open(path)?;
         let reader = BufReader::new(file);
-        for line in reader.lines() {
+        for (index, line) in reader.lines().enumerate() {
             let line = match line {
                 Ok(line_content) => line_content,
                 Err(err) => {
@@ -51,94 +139,174 @@ impl PatternTreesFactory {
                 }
             };
 
-            if line.contains(" ") || line.is_empty() || !line.is_ascii() {
+            if line.is_empty() {
+                continue;
+            }
+            let (password, count) = self.list_format.parse_line(&line)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("Line {} of {}: {}", index + 1, path, err)))?;
+            let password = normalization.normalize(password);
+            if password.contains([' ', START_SYMBOL, END_SYMBOL]) || password.is_empty() || count == 0 {
                 continue;
             }
 
-            let mut sub_strings = Self::sub_strings_max_len(line, self.count_pattern_trees);
-            sub_strings.reverse();
-            for mut sub_string in sub_strings {
-                while !sub_string.is_empty() {
-                    let split_sub_string = Self::split_end(sub_string);
-                    sub_string = split_sub_string.0;
-                    let following_letter = split_sub_string.1;
-                    let pattern_length = sub_string.len();
-                    pattern_trees[pattern_length].insert(&sub_string, Follower::new(1, following_letter));
-                }
+            if held_out_fraction.is_some_and(|fraction| ((index + 1) as f64 * fraction).floor() > (index as f64 * fraction).floor()) {
+                held_out.push((password, count));
+            } else {
+                Self::insert_password(pattern_trees, password, count);
             }
         }
-        pattern_trees[0].pattern_tree_implementation.get_mut("")
-            .unwrap()
-            .sort_unstable_by(|a, b| b.count.cmp(&a.count));
+        Ok(held_out)
+    }
 
-        Ok(PatternTrees::new(pattern_trees))
+    /// Adds every pattern and follower pair of the password `count` times.
+    pub fn insert_password(pattern_trees: &mut [PatternTree], password: String, count: u32) {
+        Self::insert_password_with(pattern_trees, password, count, |_, _, _| {});
+    }
+
+    /// Like `insert_password`, calls `on_new_pair` with the pattern tree index, the pattern and
+    /// the letter of each pair that hadn't been seen before.
+    pub fn insert_password_with(pattern_trees: &mut [PatternTree], password: String, count: u32,
+        mut on_new_pair: impl FnMut(usize, &str, char)) {
+        let password = format!("{}{}{}", START_SYMBOL, password, END_SYMBOL);
+        let mut sub_strings = Self::sub_strings_max_len(password, pattern_trees.len());
+        sub_strings.reverse();
+        for mut sub_string in sub_strings {
+            while !sub_string.is_empty() {
+                let split_sub_string = Self::split_end(sub_string);
+                sub_string = split_sub_string.0;
+                let following_letter = split_sub_string.1;
+                if following_letter == START_SYMBOL { continue; }
+                let pattern_length = sub_string.chars().count();
+                if pattern_trees[pattern_length].insert(&sub_string, Follower::new(count, following_letter)) {
+                    on_new_pair(pattern_length, &sub_string, following_letter);
+                }
+            }
+        }
     }
 
     fn sub_strings_max_len(string: String, max_len: usize) -> Vec<String> {
-        let mut sub_strings: Vec<String> = Vec::with_capacity(10);
-        let string_char_count = string.len();
-        for i in 0..string_char_count {
-            sub_strings.push(string[string_char_count - i - 1..string_char_count - i - 1 + max_len.min(i + 1)].to_string());
+        let letters: Vec<char> = string.chars().collect();
+        let mut sub_strings: Vec<String> = Vec::with_capacity(letters.len());
+        for i in 0..letters.len() {
+            let start = letters.len() - i - 1;
+            sub_strings.push(letters[start..start + max_len.min(i + 1)].iter().collect());
         }
         sub_strings
     }
 
+    /// Reads text and binary encodings alike.
     pub fn from_encoding(&self, path: &str) -> Result<PatternTrees, io::Error> {
+        if binary_encoding::has_magic(path, binary_encoding::MAGIC)? {
+            return self.from_binary_encoding(path);
+        }
+        if binary_encoding::has_magic(path, mapped_encoding::MAGIC)? {
+            return Ok(PatternTrees::from_mapped_encoding(MappedEncoding::open(path, self.count_pattern_trees)?));
+        }
         let file = File::open(path)?;
         let reader = BufReader::new(file);
         let mut pattern_length: usize = 0;
         let mut pattern_tree: PatternTree = PatternTree::new();
         let mut pattern_trees: Vec<PatternTree> = vec![];
+        let mut settings = Settings::default();
+        let mut second_format = false;
 
-        for line in reader.lines() {
-            let line = match line {
-                Ok(line_content) => line_content,
-                Err(err) => {
-                    eprintln!("ERROR: Reading line in parse_pattern_trees: {}", err);
-                    continue;
-                }
-            };
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData,
+                format!("Line {} of {}: {}", index + 1, path, message));
 
-            let line = line.trim();
-            if line.is_empty() || !line.is_ascii(){
+            if index == 0 && line == text_encoding::HEADER {
+                second_format = true;
+                continue;
+            }
+            if line.is_empty() {
                 continue
             }
-            if line == "---" {
+            if pattern_length == 0 && Settings::is_encoding_line(&line) {
+                settings.parse_encoding_line(&line).map_err(|err| invalid(err.to_string()))?;
+                continue;
+            }
+            if line == text_encoding::TREE_END {
                 pattern_trees.push(pattern_tree);
                 pattern_tree = PatternTree::new();
                 pattern_length += 1;
                 if pattern_length > self.count_pattern_trees - 1 {
-                    return Ok(PatternTrees::new(pattern_trees));
+                    break;
                 }
                 continue;
             }
 
-            let (line, count) = Self::parse_count_from_encoding(line.to_string(), pattern_length);
-            let (pattern, following_letter) = Self::split_end(line);
+            let (pattern, following_letter, count) = if second_format {
+                text_encoding::parse_record(&line).map_err(invalid)?
+            } else {
+                Self::parse_first_format_record(line, pattern_length).map_err(invalid)?
+            };
+            if pattern.chars().count() != pattern_length {
+                return Err(invalid(format!("Pattern {:?} doesn't belong to pattern tree {}", pattern, pattern_length)));
+            }
             pattern_tree.insert(&pattern, Follower::new(count, following_letter));
         }
-        Ok(PatternTrees::new(pattern_trees))
+        if pattern_trees.first().is_none_or(|pattern_tree| !pattern_tree.pattern_tree_implementation.contains_key("")) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} has no letter frequencies", path)));
+        }
+        Ok(PatternTrees::new(pattern_trees, settings))
     }
 
+    /// Like `from_encoding` only the first `count_pattern_trees` pattern trees are read.
+    pub fn from_binary_encoding(&self, path: &str) -> Result<PatternTrees, io::Error> {
+        let encoding = std::fs::read(path)?;
+        let mut input = BinaryReader::new(binary_encoding::verify_checksum(&encoding)?);
+        let metadata = Metadata::read(&mut input)?;
+        let order = (metadata.order as usize).min(self.count_pattern_trees);
+        let mut pattern_trees: Vec<PatternTree> = Vec::with_capacity(order);
+        for _ in 0..order {
+            let pattern_count = input.read_u32()?;
+            let mut patterns: Vec<(String, Vec<Follower>)> = Vec::with_capacity(pattern_count as usize);
+            let mut total_follower_count: u64 = 0;
+            for _ in 0..pattern_count {
+                let pattern = input.read_str()?.to_string();
+                let follower_count = input.read_u32()?;
+                let mut followers = Vec::with_capacity(follower_count as usize);
+                for _ in 0..follower_count {
+                    let letter = input.read_char()?;
+                    let count = input.read_u32()?;
+                    total_follower_count += count as u64;
+                    followers.push(Follower::new(count, letter));
+                }
+                patterns.push((pattern, followers));
+            }
+            pattern_trees.push(PatternTree {
+                pattern_tree_implementation: patterns.into_iter().collect(),
+                total_follower_count,
+            });
+        }
+        if order == metadata.order as usize && !input.is_at_end() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Unexpected data after the pattern trees"));
+        }
+        Ok(PatternTrees::new(pattern_trees, metadata.settings))
+    }
 
     fn split_end(mut string: String) -> (String, char) {
         let following_letter = string.pop().unwrap();
         (string, following_letter)
     }
 
+    /// Splits after `cut_off` letters, not bytes.
     fn split_off_chars(mut string: String, cut_off: usize) -> (String, String) {
-        let off_split = string.split_off(cut_off);
+        let byte_index = string.char_indices().nth(cut_off).map_or(string.len(), |(index, _)| index);
+        let off_split = string.split_off(byte_index);
         (string, off_split)
     }
 
-    fn parse_count_from_encoding(line: String, pattern_length: usize) -> (String, u32) {
+    /// Splits a line of the first text format into the pattern, the letter following it and the count.
+    fn parse_first_format_record(line: String, pattern_length: usize) -> Result<(String, char, u32), String> {
         let (line, count) = Self::split_off_chars(line, pattern_length + 1);
-        let count = count.parse::<u32>()
-            .unwrap_or_else(|err| {
-                eprintln!("ERROR: {}", err);
-                0
-            });
-        (line, count)
+        if line.chars().count() != pattern_length + 1 {
+            return Err(format!("Expected a pattern of {} letters, a letter and a count", pattern_length));
+        }
+        let count = count.parse::<u32>().map_err(|err| format!("Invalid count {}: {}", count, err))?;
+        let (pattern, following_letter) = Self::split_end(line);
+        Ok((pattern, following_letter, count))
     }
 }
 
@@ -152,6 +320,9 @@ mod tests {
         let (left, right) = PatternTreesFactory::split_off_chars(string, 3);
         assert_eq!(left, "abc".to_string());
         assert_eq!(right, "de".to_string());
+        let (left, right) = PatternTreesFactory::split_off_chars("пä密12".to_string(), 3);
+        assert_eq!(left, "пä密".to_string());
+        assert_eq!(right, "12".to_string());
     }
 
     #[test]
@@ -162,7 +333,121 @@ mod tests {
         assert_eq!(sub_strings[2], "cde".to_string());
         assert_eq!(sub_strings[3], "bcd".to_string());
         assert_eq!(sub_strings[4], "abc".to_string());
-        
+        let sub_strings = PatternTreesFactory::sub_strings_max_len("äöü密".to_string(), 2);
+        assert_eq!(sub_strings, vec!["密", "ü密", "öü", "äö"]);
+    }
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(ListFormat::Plain.parse_line("pass:word 1"), Ok(("pass:word 1", 1)));
+        assert_eq!(ListFormat::CountPrefixed.parse_line("   290729 123456"), Ok(("123456", 290729)));
+        assert_eq!(ListFormat::ColonCount.parse_line("pass:word:17"), Ok(("pass:word", 17)));
+        assert!(ListFormat::CountPrefixed.parse_line("123456").is_err());
+        assert!(ListFormat::ColonCount.parse_line("password:many").is_err());
+    }
+
+    #[test]
+    fn test_weighted_list_matches_expanded_list() {
+        let mut counts: Vec<(String, u32)> = vec![];
+        for line in std::fs::read_to_string("password_list_short.txt").unwrap().lines() {
+            match counts.iter_mut().find(|(password, _)| password == line) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((line.to_string(), 1)),
+            }
+        }
+        counts.push(("password".to_string(), 5));
+        counts.reverse();
+        let path = std::env::temp_dir().join("stochasticpwc_test_weighted_list.txt");
+        let weighted: String = counts.iter().map(|(password, count)| format!("{}:{}\n", password, count)).collect();
+        std::fs::write(&path, weighted).unwrap();
+        let mut factory = PatternTreesFactory::new(4);
+        factory.list_format = ListFormat::ColonCount;
+        let weighted_pattern_trees = factory.from_password_list(path.to_str().unwrap()).unwrap();
+
+        let expanded: String = counts.iter()
+            .flat_map(|(password, count)| (0..*count).map(move |_| format!("{}\n", password)))
+            .collect();
+        std::fs::write(&path, expanded).unwrap();
+        let expanded_pattern_trees = PatternTreesFactory::new(4).from_password_list(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        for pattern in ["", "p", "pass", "passw", "12"] {
+            let followers = |pattern_trees: &PatternTrees| -> Vec<(char, f64)> {
+                pattern_trees.follower_distribution(pattern).iter()
+                    .map(|probable_follower| (probable_follower.letter, probable_follower.probability))
+                    .collect()
+            };
+            assert_eq!(followers(&weighted_pattern_trees), followers(&expanded_pattern_trees));
+        }
+    }
+
+    #[test]
+    fn test_held_out_fraction() {
+        let mut factory = PatternTreesFactory::new(3);
+        factory.held_out_fraction = Some(0.75);
+        let pattern_trees = factory.from_password_list("password_list_short.txt").unwrap();
+        assert!(pattern_trees.settings().interpolation_weights.is_some());
+        let path = std::env::temp_dir().join("stochasticpwc_test_held_out_fraction.txt");
+        std::fs::write(&path, "\n \n\n").unwrap();
+        let error = factory.from_password_list(path.to_str().unwrap()).unwrap_err();
+        std::fs::remove_file(path).unwrap();
+        assert!(error.to_string().starts_with("No passwords"));
+        factory.settings.smoothing = Smoothing::KneserNey;
+        assert!(factory.from_password_list("password_list_short.txt").is_err());
     }
 
+    #[test]
+    fn test_add_password_list() {
+        let factory = PatternTreesFactory::new(4);
+        let path = std::env::temp_dir().join("stochasticpwc_test_add_password_list.txt");
+        let short = std::fs::read_to_string("password_list_short.txt").unwrap();
+        let unicode = std::fs::read_to_string("password_list_unicode.txt").unwrap();
+        std::fs::write(&path, format!("{}{}", short, unicode)).unwrap();
+        let combined = factory.from_password_list(path.to_str().unwrap()).unwrap();
+
+        factory.from_password_list("password_list_short.txt").unwrap().write_encoding(path.to_str().unwrap()).unwrap();
+        let updated = factory.add_password_list(factory.from_encoding(path.to_str().unwrap()).unwrap(), "password_list_unicode.txt").unwrap();
+        updated.write_encoding(path.to_str().unwrap()).unwrap();
+        let updated = factory.from_encoding(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        for (pattern_tree, combined_pattern_tree) in updated.clone().into_pattern_trees().iter().zip(combined.clone().into_pattern_trees().iter()) {
+            let count: u64 = pattern_tree.pattern_tree_implementation.values().flatten().map(|follower| follower.count as u64).sum();
+            assert_eq!(pattern_tree.total_follower_count, count);
+            assert_eq!(pattern_tree.total_follower_count, combined_pattern_tree.total_follower_count);
+        }
+        for password in ["password", "пароль", "密码123"] {
+            assert_eq!(updated.log_probability(password), combined.log_probability(password));
+        }
+    }
+
+    #[test]
+    fn test_from_encoding_formats() {
+        let factory = PatternTreesFactory::new(3);
+        let path = std::env::temp_dir().join("stochasticpwc_test_encoding_formats.txt");
+        let path = path.to_str().unwrap();
+        let first_format = "@smoothing additive\na3\nb1\n\u{3}2\n---\na\u{3}2\nab1\nb\u{3}1\n\u{2}a3\n\u{2}b1\n---\n";
+        std::fs::write(path, first_format).unwrap();
+        let pattern_trees = factory.from_encoding(path).unwrap();
+        assert_eq!(pattern_trees.settings().smoothing, crate::smoothing::Smoothing::Additive);
+        assert!(pattern_trees.has_end_symbol());
+        assert!(pattern_trees.probability("a") > pattern_trees.probability("b"));
+
+        pattern_trees.write_encoding(path).unwrap();
+        let second_format = std::fs::read_to_string(path).unwrap();
+        assert!(second_format.starts_with(text_encoding::HEADER));
+        assert!(second_format.contains("$HEX[02]\ta\t3\n"));
+        assert_eq!(factory.from_encoding(path).unwrap().probability("ab"), pattern_trees.probability("ab"));
+
+        for (encoding, line) in [
+            ("a3\nbx\n---\n".to_string(), 2),
+            (format!("{}\n\ta\t3\nb\t1\n---\n", text_encoding::HEADER), 3),
+            (format!("{}\n\ta\t3\n---\nab\tc\t1\n---\n", text_encoding::HEADER), 4),
+        ] {
+            std::fs::write(path, encoding).unwrap();
+            let err = factory.from_encoding(path).unwrap_err();
+            assert!(err.to_string().starts_with(&format!("Line {} of", line)), "{}", err);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
 }
@@ -0,0 +1,90 @@
+use std::fmt;
+
+
+/// Which followers of a pattern the enumerations expand, the end symbol is always kept.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Pruning {
+    /// 60 / (length + 1) + 1 followers for a pattern of that length
+    #[default]
+    Schedule,
+    /// The same number of followers at every position
+    TopK(usize),
+    /// The most probable followers until their probabilities add up to the given mass
+    Nucleus(f64),
+    /// One number of followers per position, the last one holds for every later position
+    PerPosition(Vec<usize>),
+}
+
+impl Pruning {
+    /// Parses the format `Display` writes: `schedule`, `top-k:20`, `nucleus:0.9` or `per-position:40,20,10`.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let (name, parameter) = value.split_once(':').unwrap_or((value, ""));
+        let count = |count: &str| count.trim().parse::<usize>()
+            .ok()
+            .filter(|count| *count > 0)
+            .ok_or_else(|| format!("{} is not a positive number of followers", count));
+        match name {
+            "schedule" if parameter.is_empty() => Ok(Self::Schedule),
+            "top-k" => Ok(Self::TopK(count(parameter)?)),
+            "nucleus" => parameter.parse::<f64>()
+                .ok()
+                .filter(|mass| *mass > 0.0 && *mass <= 1.0)
+                .map(Self::Nucleus)
+                .ok_or_else(|| format!("{} is not a probability mass above 0 and at most 1", parameter)),
+            "per-position" => Ok(Self::PerPosition(parameter.split(',').map(count).collect::<Result<_, _>>()?)),
+            _ => Err(format!("Unknown pruning {}, expected schedule, top-k:<count>, nucleus:<mass> \
+                or per-position:<count>,<count>,...", value)),
+        }
+    }
+
+    /// Most followers a pattern of this length keeps, the nucleus cuts them further by probability.
+    pub fn followers_for_pattern_length(&self, length: usize) -> usize {
+        match self {
+            Self::Schedule => 60 / (length + 1) + 1,
+            Self::TopK(count) => *count,
+            Self::Nucleus(_) => usize::MAX,
+            Self::PerPosition(counts) => counts[length.min(counts.len() - 1)],
+        }
+    }
+
+    pub fn nucleus(&self) -> Option<f64> {
+        match self {
+            Self::Nucleus(mass) => Some(*mass),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Pruning {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Schedule => write!(formatter, "schedule"),
+            Self::TopK(count) => write!(formatter, "top-k:{}", count),
+            Self::Nucleus(mass) => write!(formatter, "nucleus:{}", mass),
+            Self::PerPosition(counts) => {
+                let counts: Vec<String> = counts.iter().map(|count| count.to_string()).collect();
+                write!(formatter, "per-position:{}", counts.join(","))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pruning() {
+        for pruning in [Pruning::Schedule, Pruning::TopK(20), Pruning::Nucleus(0.95), Pruning::PerPosition(vec![40, 20, 5])] {
+            assert_eq!(Pruning::parse(&pruning.to_string()), Ok(pruning));
+        }
+        for invalid in ["top-k", "top-k:0", "nucleus:1.5", "per-position:3,,1", "schedule:3", "beam:4"] {
+            assert!(Pruning::parse(invalid).is_err(), "{}", invalid);
+        }
+        let per_position = Pruning::PerPosition(vec![40, 20, 5]);
+        let counts: Vec<usize> = (0..5).map(|length| per_position.followers_for_pattern_length(length)).collect();
+        assert_eq!(counts, vec![40, 20, 5, 5, 5]);
+        assert_eq!(Pruning::Schedule.followers_for_pattern_length(0), 61);
+        assert_eq!(Pruning::Schedule.followers_for_pattern_length(5), 11);
+    }
+}
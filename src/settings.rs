@@ -0,0 +1,94 @@
+use std::io;
+
+use clap::ValueEnum;
+
+use crate::normalization::Normalization;
+use crate::pruning::Pruning;
+use crate::smoothing::Smoothing;
+
+
+/// How passwords are normalized and probabilities are derived from the counts. They are saved with the model,
+/// so scoring and cracking with it agree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub smoothing: Smoothing,
+    pub additive_alpha: f64,
+    /// How many pattern trees below the longest fitting one are mixed.
+    pub backoff_window: usize,
+    /// One weight per pattern tree for mixing them, equal weights without.
+    pub interpolation_weights: Option<Vec<f64>>,
+    /// Applied to the passwords of the list before training and to the passwords that are scored.
+    pub normalization: Normalization,
+    /// Bits of a level, guesses are ranked by OMEN style integer levels instead of exact log probabilities with it.
+    pub level_width: Option<f64>,
+    /// Which followers the enumerations expand.
+    pub pruning: Pruning,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            smoothing: Smoothing::None,
+            additive_alpha: 1.0,
+            backoff_window: 3,
+            interpolation_weights: None,
+            normalization: Normalization::None,
+            level_width: None,
+            pruning: Pruning::Schedule,
+        }
+    }
+}
+
+impl Settings {
+    /// Lines of the form `@name value`, a pattern line never contains a space.
+    pub fn encoding_lines(&self) -> Vec<String> {
+        let mut encoding_lines = vec![
+            format!("@smoothing {}", self.smoothing.to_possible_value().unwrap().get_name()),
+            format!("@additive-alpha {}", self.additive_alpha),
+            format!("@backoff-window {}", self.backoff_window),
+            format!("@normalization {}", self.normalization.to_possible_value().unwrap().get_name()),
+        ];
+        if let Some(interpolation_weights) = &self.interpolation_weights {
+            let weights: Vec<String> = interpolation_weights.iter().map(|weight| weight.to_string()).collect();
+            encoding_lines.push(format!("@interpolation-weights {}", weights.join(" ")));
+        }
+        if let Some(level_width) = self.level_width {
+            encoding_lines.push(format!("@level-width {}", level_width));
+        }
+        if self.pruning != Pruning::Schedule {
+            encoding_lines.push(format!("@pruning {}", self.pruning));
+        }
+        encoding_lines
+    }
+
+    pub fn is_encoding_line(line: &str) -> bool {
+        line.starts_with('@') && line.contains(' ')
+    }
+
+    pub fn parse_encoding_line(&mut self, line: &str) -> Result<(), io::Error> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let (name, value) = line[1..].split_once(' ')
+            .ok_or_else(|| invalid(format!("Setting without value: {}", line)))?;
+        match name {
+            "smoothing" => self.smoothing = Smoothing::from_str(value, true).map_err(invalid)?,
+            "additive-alpha" => self.additive_alpha = value.parse()
+                .map_err(|err| invalid(format!("Invalid additive alpha {}: {}", value, err)))?,
+            "backoff-window" => self.backoff_window = value.parse()
+                .ok()
+                .filter(|backoff_window| *backoff_window > 0)
+                .ok_or_else(|| invalid(format!("Invalid backoff window {}", value)))?,
+            "normalization" => self.normalization = Normalization::from_str(value, true).map_err(invalid)?,
+            "interpolation-weights" => self.interpolation_weights = Some(value.split(' ')
+                .map(|weight| weight.parse::<f64>()
+                    .map_err(|err| invalid(format!("Invalid interpolation weight {}: {}", weight, err))))
+                .collect::<Result<Vec<f64>, io::Error>>()?),
+            "level-width" => self.level_width = Some(value.parse()
+                .ok()
+                .filter(|level_width: &f64| *level_width > 0.0 && level_width.is_finite())
+                .ok_or_else(|| invalid(format!("Invalid level width {}", value)))?),
+            "pruning" => self.pruning = Pruning::parse(value).map_err(invalid)?,
+            _ => return Err(invalid(format!("Unknown setting: {}", name))),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::BTreeMap;
+
+use clap::ValueEnum;
+
+use crate::pattern_tree::PatternTree;
+
+
+/// Counts up to this are replaced by their Good-Turing estimates, larger ones are reliable.
+const GOOD_TURING_MAX_COUNT: u64 = 5;
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Smoothing {
+    /// Letters never seen after a pattern get no probability
+    #[default]
+    None,
+    /// Adds the additive alpha to every count, an alpha of 1 is Laplace smoothing
+    Additive,
+    /// Replaces small counts by their Good-Turing estimates and gives the freed probability to unseen letters
+    GoodTuring,
+    /// Interpolated Kneser-Ney with absolute discounting and continuation counts for the lower orders
+    KneserNey,
+}
+
+/// What the smoothing needs besides the counts, derived from the pattern trees once.
+#[derive(Debug, Clone, Default)]
+pub struct SmoothingTables {
+    pub good_turing_counts: Vec<BTreeMap<u64, f64>>,
+    pub discounts: Vec<f64>,
+    /// The continuation tree of an order is derived from the tree one order higher, the highest has none.
+    pub continuation_trees: Vec<PatternTree>,
+}
+
+impl SmoothingTables {
+    /// The tables of every smoothing, for encodings that are queried without the pattern trees.
+    pub fn all(pattern_trees: &[PatternTree]) -> Self {
+        Self {
+            good_turing_counts: Self::new(Smoothing::GoodTuring, pattern_trees).good_turing_counts,
+            ..Self::new(Smoothing::KneserNey, pattern_trees)
+        }
+    }
+
+    pub fn new(smoothing: Smoothing, pattern_trees: &[PatternTree]) -> Self {
+        match smoothing {
+            Smoothing::None | Smoothing::Additive => Self::default(),
+            Smoothing::GoodTuring => Self {
+                good_turing_counts: pattern_trees.iter()
+                    .map(|pattern_tree| pattern_tree.good_turing_counts(GOOD_TURING_MAX_COUNT))
+                    .collect(),
+                ..Self::default()
+            },
+            Smoothing::KneserNey => Self {
+                discounts: pattern_trees.iter()
+                    .map(|pattern_tree| pattern_tree.kneser_ney_discount())
+                    .collect(),
+                continuation_trees: pattern_trees.iter()
+                    .skip(1)
+                    .map(|pattern_tree| pattern_tree.continuation_tree())
+                    .collect(),
+                ..Self::default()
+            },
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+
+use clap::ValueEnum;
+use md4::Md4;
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HashType {
+    /// The target is the password itself
+    #[default]
+    Plain,
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+    /// MD4 of the UTF-16LE encoded password
+    Ntlm,
+}
+
+impl HashType {
+    pub fn digest(&self, candidate: &str) -> Vec<u8> {
+        match self {
+            HashType::Plain => candidate.as_bytes().to_vec(),
+            HashType::Md5 => Md5::digest(candidate.as_bytes()).to_vec(),
+            HashType::Sha1 => Sha1::digest(candidate.as_bytes()).to_vec(),
+            HashType::Sha256 => Sha256::digest(candidate.as_bytes()).to_vec(),
+            HashType::Sha512 => Sha512::digest(candidate.as_bytes()).to_vec(),
+            HashType::Ntlm => {
+                let utf16_le: Vec<u8> = candidate.encode_utf16()
+                    .flat_map(|code_unit| code_unit.to_le_bytes())
+                    .collect();
+                Md4::digest(utf16_le).to_vec()
+            }
+        }
+    }
+
+    fn digest_len(&self) -> Option<usize> {
+        match self {
+            HashType::Plain => None,
+            HashType::Md5 | HashType::Ntlm => Some(16),
+            HashType::Sha1 => Some(20),
+            HashType::Sha256 => Some(32),
+            HashType::Sha512 => Some(64),
+        }
+    }
+
+    /// Converts a hash as given by the user into the bytes `digest` returns.
+    pub fn parse_hash(&self, hash: &str) -> Result<Vec<u8>, io::Error> {
+        let Some(digest_len) = self.digest_len() else {
+            return Ok(hash.as_bytes().to_vec());
+        };
+        let digest = hex::decode(hash.trim())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid hex digest {}: {}", hash, err)))?;
+        if digest.len() != digest_len {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("Expected a {:?} digest of {} bytes, got {} bytes", self, digest_len, digest.len())));
+        }
+        Ok(digest)
+    }
+}
+
+/// The hashes that are still to be cracked, keyed by digest and mapped to the hash as given.
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub hash_type: HashType,
+    hashes: HashMap<Vec<u8>, String>,
+}
+
+impl Target {
+    pub fn new(hash_type: HashType, hash: &str) -> Result<Self, io::Error> {
+        let mut hashes = HashMap::new();
+        hashes.insert(hash_type.parse_hash(hash)?, hash.to_string());
+        Ok(Self {
+            hash_type,
+            hashes,
+        })
+    }
+
+    /// Reads one hash per line, empty lines are skipped.
+    pub fn from_hash_list(hash_type: HashType, path: &str) -> Result<Self, io::Error> {
+        let file = File::open(path)?;
+        let reader = BufReader::new(file);
+        let mut hashes = HashMap::new();
+        for (line_index, line) in reader.lines().enumerate() {
+            let line = line?;
+            let hash = line.trim();
+            if hash.is_empty() {
+                continue;
+            }
+            let digest = hash_type.parse_hash(hash)
+                .map_err(|err| io::Error::new(err.kind(), format!("Line {} of {}: {}", line_index + 1, path, err)))?;
+            hashes.insert(digest, hash.to_string());
+        }
+        Ok(Self {
+            hash_type,
+            hashes,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    pub fn remaining_hashes(&self) -> impl Iterator<Item = &String> {
+        self.hashes.values()
+    }
+
+    pub fn is_cracked_by(&self, candidate: &str) -> bool {
+        self.hashes.contains_key(&self.hash_type.digest(candidate))
+    }
+
+    /// Returns the hash the candidate cracks and removes it from the target.
+    pub fn crack(&mut self, candidate: &str) -> Option<String> {
+        self.hashes.remove(&self.hash_type.digest(candidate))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex_digest(hash_type: HashType, candidate: &str) -> String {
+        hex::encode(hash_type.digest(candidate))
+    }
+
+    #[test]
+    fn test_digest_vectors() {
+        assert_eq!(hex_digest(HashType::Md5, ""), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(hex_digest(HashType::Md5, "password"), "5f4dcc3b5aa765d61d8327deb882cf99");
+        assert_eq!(hex_digest(HashType::Sha1, ""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(hex_digest(HashType::Sha1, "password"), "5baa61e4c9b93f3f0682250b6cf8331b7ee68fd8");
+        assert_eq!(hex_digest(HashType::Sha256, "password"),
+            "5e884898da28047151d0e56f8dc6292773603d0d6aabbdd62a11ef721d1542d8");
+        assert_eq!(hex_digest(HashType::Sha512, "password"),
+            "b109f3bbbc244eb82441917ed06d618b9008dd09b3befd1b5e07394c706a8bb9\
+             80b1d7785e5976ec049b46df5f1326af5a2ea6d103fd07c95385ffab0cacbc86");
+        assert_eq!(hex_digest(HashType::Ntlm, ""), "31d6cfe0d16ae931b73c59d7e0c089c0");
+        assert_eq!(hex_digest(HashType::Ntlm, "password"), "8846f7eaee8fb117ad06bdd830b7586c");
+    }
+
+    #[test]
+    fn test_target_crack() {
+        let mut target = Target::new(HashType::Ntlm, "8846F7EAEE8FB117AD06BDD830B7586C").unwrap();
+        assert_eq!(target.crack("Password"), None);
+        assert_eq!(target.crack("password"), Some("8846F7EAEE8FB117AD06BDD830B7586C".to_string()));
+        assert!(target.is_empty());
+        assert_eq!(target.crack("password"), None);
+        assert!(Target::new(HashType::Plain, "password").unwrap().crack("password").is_some());
+        assert!(Target::new(HashType::Md5, "5f4dcc3b").is_err());
+        assert!(Target::new(HashType::Sha1, "not hex").is_err());
+    }
+
+    #[test]
+    fn test_from_hash_list() {
+        let path = std::env::temp_dir().join("stochasticpwc_test_from_hash_list.txt");
+        std::fs::write(&path, "5f4dcc3b5aa765d61d8327deb882cf99\n\n  \n5F4DCC3B5AA765D61D8327DEB882CF99\n\
+            d41d8cd98f00b204e9800998ecf8427e  \n").unwrap();
+        let mut target = Target::from_hash_list(HashType::Md5, path.to_str().unwrap()).unwrap();
+        assert_eq!(target.len(), 2);
+        assert!(target.crack("password").is_some());
+        assert_eq!(target.crack(""), Some("d41d8cd98f00b204e9800998ecf8427e".to_string()));
+        assert!(target.is_empty());
+
+        std::fs::write(&path, "5f4dcc3b5aa765d61d8327deb882cf99\n\n5f4dcc3b\n").unwrap();
+        let error = Target::from_hash_list(HashType::Md5, path.to_str().unwrap()).unwrap_err();
+        std::fs::remove_file(path).unwrap();
+        assert!(error.to_string().starts_with("Line 3 of "), "{}", error);
+    }
+}
@@ -0,0 +1,79 @@
+/// First line of encodings in the second text format. Without it an encoding is read in the first format,
+/// where a line is the pattern, the letter and the count written one after the other.
+pub const HEADER: &str = "#stochasticpwc-encoding v2";
+/// Ends a pattern tree in both text formats.
+pub const TREE_END: &str = "---";
+
+const HEX_PREFIX: &str = "$HEX[";
+
+/// Fields with anything but printable ASCII, or that look escaped, are written as `$HEX[...]`
+/// with the hex of their UTF-8 bytes, so a record is always one line of printable ASCII.
+pub fn escape(field: &str) -> String {
+    if field.bytes().any(|byte| !byte.is_ascii_graphic()) || field.starts_with(HEX_PREFIX) {
+        format!("{}{}]", HEX_PREFIX, hex::encode(field))
+    } else {
+        field.to_string()
+    }
+}
+
+pub fn unescape(field: &str) -> Result<String, String> {
+    match field.strip_prefix(HEX_PREFIX).and_then(|hex| hex.strip_suffix(']')) {
+        Some(hex) => {
+            let bytes = hex::decode(hex).map_err(|err| format!("Invalid hex in {}: {}", field, err))?;
+            String::from_utf8(bytes).map_err(|err| format!("Invalid UTF-8 in {}: {}", field, err))
+        }
+        None => Ok(field.to_string()),
+    }
+}
+
+/// A line of the pattern, the letter following it and the count, separated by tabs.
+pub fn record(pattern: &str, letter: char, count: u32) -> String {
+    format!("{}\t{}\t{}", escape(pattern), escape(&letter.to_string()), count)
+}
+
+pub fn parse_record(line: &str) -> Result<(String, char, u32), String> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    let [pattern, letter, count] = fields[..] else {
+        return Err(format!("Expected a pattern, a letter and a count separated by tabs, got {} fields", fields.len()));
+    };
+    let pattern = unescape(pattern)?;
+    let letter = unescape(letter)?;
+    let mut letters = letter.chars();
+    let (Some(following_letter), None) = (letters.next(), letters.next()) else {
+        return Err(format!("Expected a single letter, got {:?}", letter));
+    };
+    let count = count.parse::<u32>().map_err(|err| format!("Invalid count {}: {}", count, err))?;
+    Ok((pattern, following_letter, count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("pass"), "pass");
+        assert_eq!(escape(""), "");
+        assert_eq!(escape("\u{2}p"), "$HEX[0270]");
+        assert_eq!(escape("a b"), "$HEX[612062]");
+        assert_eq!(escape("$HEX[41]"), "$HEX[244845585b34315d]");
+        for field in ["pass", "", "---", "a\tb", "a\nb", "\r", "$HEX[41]", "$HEX[", "straße", "密码", "\u{3}"] {
+            let escaped = escape(field);
+            assert!(escaped.bytes().all(|byte| byte.is_ascii_graphic()));
+            assert_eq!(unescape(&escaped).unwrap(), field);
+        }
+        assert!(unescape("$HEX[4]").is_err());
+        assert!(unescape("$HEX[ff]").is_err());
+    }
+
+    #[test]
+    fn test_parse_record() {
+        assert_eq!(parse_record(&record("---", '\n', 7)).unwrap(), ("---".to_string(), '\n', 7));
+        assert_eq!(parse_record(&record("", 'ß', 1)).unwrap(), ("".to_string(), 'ß', 1));
+        assert_eq!(parse_record("pas\ts\t12").unwrap(), ("pas".to_string(), 's', 12));
+        assert!(parse_record("pas\ts").is_err());
+        assert!(parse_record("pas\tst\t1").is_err());
+        assert!(parse_record("pas\ts\t-1").is_err());
+        assert!(parse_record("pas\ts\t1\t").is_err());
+    }
+}
@@ -0,0 +1,169 @@
+use crate::budget::Budget;
+use crate::crack::CrackedHash;
+use crate::pattern_tree::END_SYMBOL;
+use crate::pattern_trees::PatternTrees;
+use crate::target::Target;
+
+
+/// Visits the words whose base 2 log probability lies in a range, depth first without pruning
+/// followers by count. Followers are tried most probable first and a pattern is only extended while
+/// it's at least as probable as the lower bound, since appending a letter never makes it more probable.
+/// Only the distributions along the current pattern are held, so memory grows with `max_len` alone.
+struct ThresholdSearch<'a> {
+    pattern_trees: &'a PatternTrees,
+    max_len: usize,
+    pattern: String,
+}
+
+impl<'a> ThresholdSearch<'a> {
+    fn new(pattern_trees: &'a PatternTrees, max_len: usize) -> Self {
+        Self {
+            pattern_trees,
+            max_len,
+            pattern: String::new(),
+        }
+    }
+
+    /// Calls `try_password` with every word of at most `max_len` letters with a log probability of
+    /// at least `min` and below `max`. Returns false once `try_password` does, which stops the search.
+    fn visit(&mut self, min: f64, max: f64, try_password: &mut impl FnMut(&str) -> bool) -> bool {
+        self.visit_pattern(0, 0.0, min, max, try_password)
+    }
+
+    fn visit_pattern(&mut self, letter_count: usize, log_probability: f64, min: f64, max: f64,
+        try_password: &mut impl FnMut(&str) -> bool) -> bool {
+        let pattern_trees = self.pattern_trees;
+        if !pattern_trees.has_end_symbol() && log_probability < max && !try_password(&self.pattern) {
+            return false;
+        }
+        if letter_count >= self.max_len {
+            if pattern_trees.has_end_symbol() {
+                let end_probability = pattern_trees.follower_probability(&self.pattern, END_SYMBOL);
+                let log_probability = log_probability + pattern_trees.log_step(end_probability);
+                if log_probability >= min && log_probability < max {
+                    return try_password(&self.pattern);
+                }
+            }
+            return true;
+        }
+        let pattern_len = self.pattern.len();
+        for follower in pattern_trees.follower_distribution(&self.pattern) {
+            let log_probability = log_probability + pattern_trees.log_step(follower.probability);
+            if log_probability < min {
+                break;
+            }
+            let keep_going = if follower.letter == END_SYMBOL {
+                log_probability >= max || try_password(&self.pattern)
+            } else {
+                self.pattern.push(follower.letter);
+                let keep_going = self.visit_pattern(letter_count + 1, log_probability, min, max, try_password);
+                self.pattern.truncate(pattern_len);
+                keep_going
+            };
+            if !keep_going {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Number of words with a log probability of at least `min` and below `max` within the lengths of the budget,
+/// counted until the budget runs out of time.
+fn count_guesses_between(pattern_trees: &PatternTrees, budget: &Budget, min: f64, max: f64) -> u64 {
+    let mut count: u64 = 0;
+    ThresholdSearch::new(pattern_trees, budget.max_len).visit(min, max, &mut |password| {
+        if budget.is_long_enough(password) {
+            count += 1;
+        }
+        !count.is_multiple_of(1024) || !budget.is_out_of_time()
+    });
+    count
+}
+
+/// Tries every word at least as probable as the threshold by iterative deepening: each round lowers
+/// the bound by the factor `threshold_step` and tries the words between it and the bound of the round
+/// before, so the more probable words come first. The number of guesses of a round is counted before
+/// it starts.
+pub fn crack_threshold(pattern_trees: PatternTrees, budget: &Budget, mut target: Target, threshold: f64,
+    threshold_step: f64) -> Vec<CrackedHash> {
+    let mut cracked_hashes = vec![];
+    let mut search = ThresholdSearch::new(&pattern_trees, budget.max_len);
+    let mut max = f64::INFINITY;
+    let mut round = 0;
+    while !target.is_empty() && !budget.is_exhausted() && max > threshold.log2() {
+        round += 1;
+        let round_threshold = threshold_step.powi(-round).max(threshold);
+        let round_min = round_threshold.log2();
+        let round_guesses = count_guesses_between(&pattern_trees, budget, round_min, max);
+        eprintln!("INFO: {} guesses with a probability from {:e} on", round_guesses, round_threshold);
+        search.visit(round_min, max, &mut |password| {
+            if !budget.is_long_enough(password) {
+                return true;
+            }
+            let Some(guess) = budget.next_guess() else { return false; };
+            if let Some(hash) = target.crack(password) {
+                cracked_hashes.push(CrackedHash::new(hash, password.to_string(), guess));
+            }
+            !target.is_empty()
+        });
+        max = round_min;
+    }
+    cracked_hashes
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::pattern_trees_factory::PatternTreesFactory;
+    use crate::target::HashType;
+
+    #[test]
+    fn test_threshold_search_finds_probable_words() {
+        let pattern_trees = PatternTreesFactory::new(3).from_password_list("password_list_short.txt").unwrap();
+        let min = 1e-4_f64.log2();
+        let mut words = vec![];
+        ThresholdSearch::new(&pattern_trees, 5).visit(min, f64::INFINITY, &mut |password| {
+            words.push(password.to_string());
+            true
+        });
+        assert!(words.len() > 10);
+        let unique_words: HashSet<&String> = words.iter().collect();
+        assert_eq!(unique_words.len(), words.len());
+        for word in words.iter() {
+            assert!(word.chars().count() <= 5);
+            assert!(pattern_trees.log_probability(word) >= min - 1e-9);
+        }
+        // A found word with one more letter is found too if it's still probable enough.
+        let mut missing = 0;
+        for word in words.iter().filter(|word| word.chars().count() < 5) {
+            for follower in pattern_trees.follower_distribution(word).iter().filter(|follower| follower.letter != END_SYMBOL) {
+                let longer = format!("{}{}", word, follower.letter);
+                if pattern_trees.log_probability(&longer) >= min + 1e-9 && !unique_words.contains(&longer) {
+                    missing += 1;
+                }
+            }
+        }
+        assert_eq!(missing, 0);
+        let budget = Budget::new(0, 5, None, None);
+        assert_eq!(count_guesses_between(&pattern_trees, &budget, min, f64::INFINITY), words.len() as u64);
+        assert_eq!(count_guesses_between(&pattern_trees, &budget, (1e-2_f64).log2(), f64::INFINITY)
+            + count_guesses_between(&pattern_trees, &budget, min, (1e-2_f64).log2()), words.len() as u64);
+    }
+
+    #[test]
+    fn test_crack_threshold() {
+        let pattern_trees = PatternTreesFactory::new(3).from_password_list("password_list_short.txt").unwrap();
+        let target = Target::new(HashType::Md5, "1a1dc91c907325c69271ddf0c944bc72").unwrap();
+        let budget = Budget::new(0, 4, None, None);
+        let cracked_hashes = crack_threshold(pattern_trees.clone(), &budget, target, 1e-12, 10.0);
+        assert_eq!(cracked_hashes.len(), 1);
+        assert_eq!(cracked_hashes[0].password, "pass");
+        let min = (pattern_trees.probability("pass") / 10.0).log2();
+        assert!(cracked_hashes[0].guesses <= count_guesses_between(&pattern_trees, &budget, min, f64::INFINITY));
+        let target = Target::new(HashType::Plain, "not in the search space").unwrap();
+        assert!(crack_threshold(pattern_trees, &Budget::new(0, 4, None, None), target, 1e-6, 10.0).is_empty());
+    }
+}